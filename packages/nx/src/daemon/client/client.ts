@@ -33,7 +33,21 @@ import {
   DaemonProjectGraphError,
   ProjectGraphError,
 } from '../../project-graph/error-types';
-import { IS_WASM, NxWorkspaceFiles, TaskRun, TaskTarget } from '../../native';
+import {
+  FlakyTarget,
+  FlakyTargetOptions,
+  IS_WASM,
+  NxWorkspaceFiles,
+  TaskGraph,
+  TaskGraphEstimate,
+  TaskGraphEstimateOptions,
+  TaskRun,
+  TaskRunQuery,
+  TaskRunQueryResult,
+  TaskTarget,
+  TaskTimingOptions,
+  TaskTimingStats,
+} from '../../native';
 import { HandleGlobMessage } from '../message-types/glob';
 import {
   GET_NX_WORKSPACE_FILES,
@@ -49,11 +63,19 @@ import {
 } from '../message-types/get-files-in-directory';
 import { HASH_GLOB, HandleHashGlobMessage } from '../message-types/hash-glob';
 import {
+  ESTIMATE_TASK_GRAPH,
   GET_ESTIMATED_TASK_TIMINGS,
+  GET_FLAKY_TARGETS,
   GET_FLAKY_TASKS,
+  GET_TASK_TIMING_STATS,
+  HandleEstimateTaskGraph,
   HandleGetEstimatedTaskTimings,
+  HandleGetFlakyTargets,
   HandleGetFlakyTasks,
+  HandleGetTaskTimingStats,
+  HandleQueryTaskRunsMessage,
   HandleRecordTaskRunsMessage,
+  QUERY_TASK_RUNS,
   RECORD_TASK_RUNS,
 } from '../message-types/task-history';
 import { FORCE_SHUTDOWN } from '../message-types/force-shutdown';
@@ -352,6 +374,15 @@ export class DaemonClient {
     return this.sendToDaemonViaQueue(message);
   }
 
+  queryTaskRuns(query: TaskRunQuery): Promise<TaskRunQueryResult> {
+    const message: HandleQueryTaskRunsMessage = {
+      type: QUERY_TASK_RUNS,
+      query,
+    };
+
+    return this.sendToDaemonViaQueue(message);
+  }
+
   getFlakyTasks(hashes: string[]): Promise<string[]> {
     const message: HandleGetFlakyTasks = {
       type: GET_FLAKY_TASKS,
@@ -361,6 +392,15 @@ export class DaemonClient {
     return this.sendToDaemonViaQueue(message);
   }
 
+  getFlakyTargets(options?: FlakyTargetOptions): Promise<FlakyTarget[]> {
+    const message: HandleGetFlakyTargets = {
+      type: GET_FLAKY_TARGETS,
+      options,
+    };
+
+    return this.sendToDaemonViaQueue(message);
+  }
+
   async getEstimatedTaskTimings(
     targets: TaskTarget[]
   ): Promise<Record<string, number>> {
@@ -372,6 +412,34 @@ export class DaemonClient {
     return this.sendToDaemonViaQueue(message);
   }
 
+  async getTaskTimingStats(
+    targets: TaskTarget[],
+    options?: TaskTimingOptions
+  ): Promise<Record<string, TaskTimingStats>> {
+    const message: HandleGetTaskTimingStats = {
+      type: GET_TASK_TIMING_STATS,
+      targets,
+      options,
+    };
+
+    return this.sendToDaemonViaQueue(message);
+  }
+
+  async estimateTaskGraph(
+    taskGraph: TaskGraph,
+    parallel: number,
+    options?: TaskGraphEstimateOptions
+  ): Promise<TaskGraphEstimate> {
+    const message: HandleEstimateTaskGraph = {
+      type: ESTIMATE_TASK_GRAPH,
+      taskGraph,
+      parallel,
+      options,
+    };
+
+    return this.sendToDaemonViaQueue(message);
+  }
+
   recordTaskRuns(taskRuns: TaskRun[]): Promise<void> {
     const message: HandleRecordTaskRunsMessage = {
       type: RECORD_TASK_RUNS,
@@ -1,24 +1,59 @@
-import type { TaskRun, TaskTarget } from '../../native';
+import type {
+  FlakyTargetOptions,
+  TaskGraph,
+  TaskGraphEstimateOptions,
+  TaskRun,
+  TaskRunQuery,
+  TaskTarget,
+  TaskTimingOptions,
+} from '../../native';
 
 export const GET_FLAKY_TASKS = 'GET_FLAKY_TASKS' as const;
+export const GET_FLAKY_TARGETS = 'GET_FLAKY_TARGETS' as const;
 export const GET_ESTIMATED_TASK_TIMINGS = 'GET_ESTIMATED_TASK_TIMINGS' as const;
+export const GET_TASK_TIMING_STATS = 'GET_TASK_TIMING_STATS' as const;
+export const ESTIMATE_TASK_GRAPH = 'ESTIMATE_TASK_GRAPH' as const;
 export const RECORD_TASK_RUNS = 'RECORD_TASK_RUNS' as const;
+export const QUERY_TASK_RUNS = 'QUERY_TASK_RUNS' as const;
 
 export type HandleGetFlakyTasks = {
   type: typeof GET_FLAKY_TASKS;
   hashes: string[];
 };
 
+export type HandleGetFlakyTargets = {
+  type: typeof GET_FLAKY_TARGETS;
+  options?: FlakyTargetOptions;
+};
+
 export type HandleGetEstimatedTaskTimings = {
   type: typeof GET_ESTIMATED_TASK_TIMINGS;
   targets: TaskTarget[];
 };
 
+export type HandleGetTaskTimingStats = {
+  type: typeof GET_TASK_TIMING_STATS;
+  targets: TaskTarget[];
+  options?: TaskTimingOptions;
+};
+
+export type HandleEstimateTaskGraph = {
+  type: typeof ESTIMATE_TASK_GRAPH;
+  taskGraph: TaskGraph;
+  parallel: number;
+  options?: TaskGraphEstimateOptions;
+};
+
 export type HandleRecordTaskRunsMessage = {
   type: typeof RECORD_TASK_RUNS;
   taskRuns: TaskRun[];
 };
 
+export type HandleQueryTaskRunsMessage = {
+  type: typeof QUERY_TASK_RUNS;
+  query: TaskRunQuery;
+};
+
 export function isHandleGetFlakyTasksMessage(
   message: unknown
 ): message is HandleGetFlakyTasks {
@@ -30,6 +65,17 @@ export function isHandleGetFlakyTasksMessage(
   );
 }
 
+export function isHandleGetFlakyTargetsMessage(
+  message: unknown
+): message is HandleGetFlakyTargets {
+  return (
+    typeof message === 'object' &&
+    message !== null &&
+    'type' in message &&
+    message['type'] === GET_FLAKY_TARGETS
+  );
+}
+
 export function isHandleGetEstimatedTaskTimings(
   message: unknown
 ): message is HandleGetEstimatedTaskTimings {
@@ -41,6 +87,28 @@ export function isHandleGetEstimatedTaskTimings(
   );
 }
 
+export function isHandleGetTaskTimingStats(
+  message: unknown
+): message is HandleGetTaskTimingStats {
+  return (
+    typeof message === 'object' &&
+    message !== null &&
+    'type' in message &&
+    message['type'] === GET_TASK_TIMING_STATS
+  );
+}
+
+export function isHandleEstimateTaskGraph(
+  message: unknown
+): message is HandleEstimateTaskGraph {
+  return (
+    typeof message === 'object' &&
+    message !== null &&
+    'type' in message &&
+    message['type'] === ESTIMATE_TASK_GRAPH
+  );
+}
+
 export function isHandleWriteTaskRunsToHistoryMessage(
   message: unknown
 ): message is HandleRecordTaskRunsMessage {
@@ -51,3 +119,14 @@ export function isHandleWriteTaskRunsToHistoryMessage(
     message['type'] === RECORD_TASK_RUNS
   );
 }
+
+export function isHandleQueryTaskRunsMessage(
+  message: unknown
+): message is HandleQueryTaskRunsMessage {
+  return (
+    typeof message === 'object' &&
+    message !== null &&
+    'type' in message &&
+    message['type'] === QUERY_TASK_RUNS
+  );
+}
@@ -1,5 +1,13 @@
 import { getTaskHistory } from '../../utils/task-history';
-import type { TaskRun, TaskTarget } from '../../native';
+import type {
+  FlakyTargetOptions,
+  TaskGraph,
+  TaskGraphEstimateOptions,
+  TaskRun,
+  TaskRunQuery,
+  TaskTarget,
+  TaskTimingOptions,
+} from '../../native';
 
 export async function handleRecordTaskRuns(taskRuns: TaskRun[]) {
   const taskHistory = getTaskHistory();
@@ -10,6 +18,15 @@ export async function handleRecordTaskRuns(taskRuns: TaskRun[]) {
   };
 }
 
+export async function handleQueryTaskRuns(query: TaskRunQuery) {
+  const taskHistory = getTaskHistory();
+  const result = await taskHistory.queryTaskRuns(query);
+  return {
+    response: JSON.stringify(result),
+    description: 'handleQueryTaskRuns',
+  };
+}
+
 export async function handleGetFlakyTasks(hashes: string[]) {
   const taskHistory = getTaskHistory();
   const history = await taskHistory.getFlakyTasks(hashes);
@@ -19,6 +36,15 @@ export async function handleGetFlakyTasks(hashes: string[]) {
   };
 }
 
+export async function handleGetFlakyTargets(options?: FlakyTargetOptions) {
+  const taskHistory = getTaskHistory();
+  const flakyTargets = await taskHistory.getFlakyTargets(options);
+  return {
+    response: JSON.stringify(flakyTargets),
+    description: 'handleGetFlakyTargets',
+  };
+}
+
 export async function handleGetEstimatedTaskTimings(targets: TaskTarget[]) {
   const taskHistory = getTaskHistory();
   const history = await taskHistory.getEstimatedTaskTimings(targets);
@@ -27,3 +53,32 @@ export async function handleGetEstimatedTaskTimings(targets: TaskTarget[]) {
     description: 'handleGetEstimatedTaskTimings',
   };
 }
+
+export async function handleGetTaskTimingStats(
+  targets: TaskTarget[],
+  options?: TaskTimingOptions
+) {
+  const taskHistory = getTaskHistory();
+  const stats = await taskHistory.getTaskTimingStats(targets, options);
+  return {
+    response: JSON.stringify(stats),
+    description: 'handleGetTaskTimingStats',
+  };
+}
+
+export async function handleEstimateTaskGraph(
+  taskGraph: TaskGraph,
+  parallel: number,
+  options?: TaskGraphEstimateOptions
+) {
+  const taskHistory = getTaskHistory();
+  const estimate = await taskHistory.estimateTaskGraph(
+    taskGraph,
+    parallel,
+    options
+  );
+  return {
+    response: JSON.stringify(estimate),
+    description: 'handleEstimateTaskGraph',
+  };
+}
@@ -74,17 +74,29 @@ import { handleGetFilesInDirectory } from './handle-get-files-in-directory';
 import { HASH_GLOB, isHandleHashGlobMessage } from '../message-types/hash-glob';
 import { handleHashGlob } from './handle-hash-glob';
 import {
+  ESTIMATE_TASK_GRAPH,
   GET_ESTIMATED_TASK_TIMINGS,
+  GET_FLAKY_TARGETS,
   GET_FLAKY_TASKS,
+  GET_TASK_TIMING_STATS,
+  isHandleEstimateTaskGraph,
   isHandleGetEstimatedTaskTimings,
+  isHandleGetFlakyTargetsMessage,
   isHandleGetFlakyTasksMessage,
+  isHandleGetTaskTimingStats,
+  isHandleQueryTaskRunsMessage,
   isHandleWriteTaskRunsToHistoryMessage,
+  QUERY_TASK_RUNS,
   RECORD_TASK_RUNS,
 } from '../message-types/task-history';
 import {
   handleRecordTaskRuns,
+  handleQueryTaskRuns,
   handleGetFlakyTasks,
+  handleGetFlakyTargets,
   handleGetEstimatedTaskTimings,
+  handleGetTaskTimingStats,
+  handleEstimateTaskGraph,
 } from './handle-task-history';
 import { isHandleForceShutdownMessage } from '../message-types/force-shutdown';
 import { handleForceShutdown } from './handle-force-shutdown';
@@ -248,14 +260,34 @@ async function handleMessage(socket, data: string) {
     await handleResult(socket, GET_FLAKY_TASKS, () =>
       handleGetFlakyTasks(payload.hashes)
     );
+  } else if (isHandleGetFlakyTargetsMessage(payload)) {
+    await handleResult(socket, GET_FLAKY_TARGETS, () =>
+      handleGetFlakyTargets(payload.options)
+    );
   } else if (isHandleGetEstimatedTaskTimings(payload)) {
     await handleResult(socket, GET_ESTIMATED_TASK_TIMINGS, () =>
       handleGetEstimatedTaskTimings(payload.targets)
     );
+  } else if (isHandleGetTaskTimingStats(payload)) {
+    await handleResult(socket, GET_TASK_TIMING_STATS, () =>
+      handleGetTaskTimingStats(payload.targets, payload.options)
+    );
+  } else if (isHandleEstimateTaskGraph(payload)) {
+    await handleResult(socket, ESTIMATE_TASK_GRAPH, () =>
+      handleEstimateTaskGraph(
+        payload.taskGraph,
+        payload.parallel,
+        payload.options
+      )
+    );
   } else if (isHandleWriteTaskRunsToHistoryMessage(payload)) {
     await handleResult(socket, RECORD_TASK_RUNS, () =>
       handleRecordTaskRuns(payload.taskRuns)
     );
+  } else if (isHandleQueryTaskRunsMessage(payload)) {
+    await handleResult(socket, QUERY_TASK_RUNS, () =>
+      handleQueryTaskRuns(payload.query)
+    );
   } else if (isHandleForceShutdownMessage(payload)) {
     await handleResult(socket, 'FORCE_SHUTDOWN', () =>
       handleForceShutdown(server)
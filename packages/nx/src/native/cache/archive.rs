@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{metadata, rename, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tracing::trace;
+
+use crate::native::cache::encryption::CacheCipher;
+use crate::native::cache::manifest::{
+    apply_metadata, manifest_from_bytes, manifest_to_bytes, CacheManifest, ManifestEntry,
+    ManifestEntryKind, MANIFEST_FILE,
+};
+use crate::native::hasher::hash_reader;
+
+/// Name of the terminal output file stored inside of cache archives
+const TERMINAL_OUTPUT_FILE: &str = ".nx-terminal-output";
+/// Name of the file holding the exit code of the task inside of cache archives
+const CODE_FILE: &str = ".nx-code";
+
+type ArchiveReader = tar::Archive<zstd::Decoder<'static, BufReader<Box<dyn Read>>>>;
+
+pub fn get_archive_path(cache_path: &Path, hash: &str) -> PathBuf {
+    cache_path.join(format!("{}.tar.zst", hash))
+}
+
+/// Writes a compressed archive containing the manifest, the exit code, the terminal output
+/// and every entry of the manifest, read from `workspace_root`. Returns the size of the archive.
+///
+/// The manifest, exit code and terminal output are written first so that they can be read
+/// without decompressing the rest of the archive.
+/// With a cipher, the compressed archive is encrypted.
+pub fn write_archive(
+    archive_path: &Path,
+    workspace_root: &Path,
+    manifest: &CacheManifest,
+    code: i16,
+    terminal_output: &str,
+    cipher: Option<&CacheCipher>,
+) -> anyhow::Result<u64> {
+    trace!("Writing cache archive: {:?}", archive_path);
+    let tmp_path = archive_path.with_extension(format!("{}.tmp", process::id()));
+
+    let file = BufWriter::new(File::create(&tmp_path)?);
+    let mut file = match cipher {
+        Some(cipher) => {
+            let writer = cipher.encrypt(file)?;
+            write_archive_contents(writer, workspace_root, manifest, code, terminal_output)?
+                .finish()?
+        }
+        None => write_archive_contents(file, workspace_root, manifest, code, terminal_output)?,
+    };
+    file.flush()?;
+    rename(&tmp_path, archive_path)?;
+
+    Ok(metadata(archive_path)?.len())
+}
+
+fn write_archive_contents<W: Write>(
+    writer: W,
+    workspace_root: &Path,
+    manifest: &CacheManifest,
+    code: i16,
+    terminal_output: &str,
+) -> anyhow::Result<W> {
+    let encoder = zstd::Encoder::new(writer, 0)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+
+    append_bytes(&mut builder, MANIFEST_FILE, &manifest_to_bytes(manifest)?)?;
+    append_bytes(&mut builder, CODE_FILE, code.to_string().as_bytes())?;
+    append_bytes(
+        &mut builder,
+        TERMINAL_OUTPUT_FILE,
+        terminal_output.as_bytes(),
+    )?;
+    for entry in manifest.entries.iter() {
+        append_entry(&mut builder, workspace_root, entry)?;
+    }
+
+    Ok(builder.into_inner()?.finish()?)
+}
+
+fn append_bytes<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, bytes)
+}
+
+/// Appends an entry of the manifest with the mode and modification time recorded in the manifest,
+/// since the files of entries which share their blobs do not have reliable metadata
+fn append_entry<W: Write>(
+    builder: &mut tar::Builder<W>,
+    root: &Path,
+    entry: &ManifestEntry,
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(entry.mode);
+    header.set_mtime(entry.mtime.div_euclid(1_000_000_000).max(0) as u64);
+    match &entry.kind {
+        ManifestEntryKind::File { .. } => {
+            let file = File::open(root.join(&entry.path))?;
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(file.metadata()?.len());
+            builder.append_data(&mut header, &entry.path, file)
+        }
+        ManifestEntryKind::Directory => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, &entry.path, std::io::empty())
+        }
+        ManifestEntryKind::Symlink { target } => {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, &entry.path, target)
+        }
+    }
+}
+
+/// Fails if the archive is not encrypted with the given cipher, or is encrypted without one being given
+fn open_archive(
+    archive_path: &Path,
+    cipher: Option<&CacheCipher>,
+) -> anyhow::Result<ArchiveReader> {
+    let file = BufReader::new(File::open(archive_path)?);
+    let reader: Box<dyn Read> = match cipher {
+        Some(cipher) => Box::new(cipher.decrypt(file)?),
+        None => Box::new(file),
+    };
+    let decoder = zstd::Decoder::new(reader)?;
+    Ok(tar::Archive::new(decoder))
+}
+
+/// Reads the exit code and terminal output stored in an archive
+pub fn read_archive_result(
+    archive_path: &Path,
+    cipher: Option<&CacheCipher>,
+) -> anyhow::Result<(i16, String)> {
+    let mut archive = open_archive(archive_path, cipher)?;
+    let mut code = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path == Path::new(CODE_FILE) {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            code = Some(contents.trim().parse::<i16>()?);
+        } else if path == Path::new(TERMINAL_OUTPUT_FILE) {
+            let mut terminal_output = String::new();
+            entry.read_to_string(&mut terminal_output)?;
+            let code = code.ok_or_else(|| {
+                anyhow::anyhow!("{:?} does not contain an exit code", archive_path)
+            })?;
+            return Ok((code, terminal_output));
+        }
+    }
+    Err(anyhow::anyhow!(
+        "{:?} does not contain a terminal output",
+        archive_path
+    ))
+}
+
+/// Streams the contents of an archive into `destination`.
+/// `before_extract` is called with the manifest of the archive before any file is written,
+/// and returns the paths which should not be extracted.
+pub fn extract_archive<F>(
+    archive_path: &Path,
+    destination: &Path,
+    cipher: Option<&CacheCipher>,
+    before_extract: F,
+) -> anyhow::Result<()>
+where
+    F: FnOnce(&CacheManifest) -> anyhow::Result<HashSet<PathBuf>>,
+{
+    trace!(
+        "Extracting cache archive {:?} -> {:?}",
+        archive_path,
+        destination
+    );
+    let mut before_extract = Some(before_extract);
+    let mut manifest = None;
+    let mut skipped_paths = HashSet::new();
+    let mut unpacked_paths = HashSet::new();
+    let mut archive = open_archive(archive_path, cipher)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        if path == Path::new(MANIFEST_FILE) {
+            let mut bytes = vec![];
+            entry.read_to_end(&mut bytes)?;
+            if let Some(before_extract) = before_extract.take() {
+                let archive_manifest = manifest_from_bytes(&bytes)?;
+                skipped_paths = before_extract(&archive_manifest)?;
+                manifest = Some(archive_manifest);
+            }
+        } else if path != Path::new(TERMINAL_OUTPUT_FILE)
+            && path != Path::new(CODE_FILE)
+            && !skipped_paths.contains(&path)
+            && entry.unpack_in(destination)?
+        {
+            unpacked_paths.insert(path);
+        }
+    }
+
+    // Archives only hold whole seconds, so the exact metadata is applied from the manifest.
+    // Children are handled before their directories, since writing them changes the directory.
+    // Files which were not extracted are left untouched, and paths which are not in the archive are never touched.
+    if let Some(manifest) = manifest {
+        for entry in manifest.entries.iter().rev() {
+            let path = Path::new(&entry.path);
+            let is_dir = matches!(entry.kind, ManifestEntryKind::Directory);
+            if unpacked_paths.contains(path) || (is_dir && skipped_paths.contains(path)) {
+                apply_metadata(&destination.join(path), entry)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that every file of the archive's manifest is present in the archive
+/// and still has the hash which was recorded when the archive was written.
+pub fn verify_archive(archive_path: &Path, cipher: Option<&CacheCipher>) -> anyhow::Result<bool> {
+    let mut archive = open_archive(archive_path, cipher)?;
+    let mut expected_files: Option<HashMap<PathBuf, String>> = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        if path == Path::new(MANIFEST_FILE) {
+            let mut bytes = vec![];
+            entry.read_to_end(&mut bytes)?;
+            let manifest = manifest_from_bytes(&bytes)?;
+            expected_files = Some(
+                manifest
+                    .entries
+                    .into_iter()
+                    .filter_map(|entry| match entry.kind {
+                        ManifestEntryKind::File { blob, .. } => {
+                            Some((PathBuf::from(entry.path), blob))
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+            );
+            continue;
+        }
+
+        let Some(expected_blob) = expected_files
+            .as_mut()
+            .and_then(|files| files.remove(&path))
+        else {
+            continue;
+        };
+        if hash_reader(&mut entry)? != expected_blob {
+            trace!("{:?} in {:?} does not match its hash", path, archive_path);
+            return Ok(false);
+        }
+    }
+
+    match expected_files {
+        Some(missing_files) if missing_files.is_empty() => Ok(true),
+        Some(missing_files) => {
+            trace!(
+                "{:?} is missing files: {:?}",
+                archive_path,
+                missing_files.keys()
+            );
+            Ok(false)
+        }
+        None => {
+            trace!("{:?} does not contain a manifest", archive_path);
+            Ok(false)
+        }
+    }
+}
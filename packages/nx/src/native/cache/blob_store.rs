@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::trace;
+
+use crate::native::cache::cache::CopyStrategy;
+use crate::native::db::connection::NxDbConnection;
+use crate::native::hasher::hash_file_path;
+
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A different file with the same hash is already stored.
+/// Blobs are keyed by a non-cryptographic hash, so their contents are compared before they are shared.
+#[derive(Debug)]
+pub struct BlobCollision {
+    pub blob: String,
+}
+
+impl fmt::Display for BlobCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The blob {} is already stored with different contents",
+            self.blob
+        )
+    }
+}
+
+impl std::error::Error for BlobCollision {}
+
+/// Content addressed storage for the files of cache entries.
+///
+/// Every distinct file is stored once under `blobs/<prefix>/<hash>` and hardlinked
+/// into the entries which contain it. The `cache_blobs` table keeps track of how many
+/// entries reference each blob so that it can be removed with the last of them.
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(cache_path: &Path) -> anyhow::Result<Self> {
+        let root = cache_path.join("blobs");
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn setup(&self, db: &NxDbConnection) -> anyhow::Result<()> {
+        db.create_table(
+            "cache_blobs",
+            "CREATE TABLE IF NOT EXISTS cache_blobs (
+                blob    TEXT PRIMARY KEY NOT NULL,
+                size    INTEGER NOT NULL,
+                refs    INTEGER NOT NULL DEFAULT 0
+            );",
+        )?;
+        Ok(())
+    }
+
+    pub fn blob_path(&self, blob: &str) -> PathBuf {
+        let prefix = blob.get(..2).unwrap_or(blob);
+        self.root.join(prefix).join(blob)
+    }
+
+    /// Stores a copy of `src`, which hashes to `blob`, and links the stored blob to `dest`
+    pub fn insert(
+        &self,
+        src: &Path,
+        blob: &str,
+        dest: &Path,
+        strategy: CopyStrategy,
+    ) -> anyhow::Result<()> {
+        let blob_path = self.blob_path(blob);
+
+        if blob_path.exists() {
+            self.check_contents(&blob_path, src, blob)?;
+        } else {
+            trace!("Storing new blob {} from {:?}", blob, src);
+            let blob_dir = blob_path.parent().unwrap_or(&self.root);
+            fs::create_dir_all(blob_dir)?;
+            // Write to a temporary file first so that a blob is never visible half written
+            let tmp_path = blob_dir.join(format!(
+                "{}.{}-{}.tmp",
+                blob,
+                process::id(),
+                TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            // Linking the blob to `src` would let the workspace modify it in place
+            let store_strategy = match strategy {
+                CopyStrategy::Reflink => CopyStrategy::Reflink,
+                CopyStrategy::Copy | CopyStrategy::Hardlink => CopyStrategy::Copy,
+            };
+            copy_file(src, &tmp_path, store_strategy)?;
+            if strategy == CopyStrategy::Hardlink {
+                // Restored outputs are linked to the blob, so none of them may modify it in place
+                make_read_only(&tmp_path)?;
+            }
+            fs::rename(&tmp_path, &blob_path)?;
+        }
+
+        link_or_copy(&blob_path, dest)?;
+        Ok(())
+    }
+
+    /// Moves an existing file into the blob store, replacing it with a link to the stored blob
+    pub fn adopt(&self, path: &Path) -> anyhow::Result<String> {
+        let blob =
+            hash_file_path(path).ok_or_else(|| anyhow::anyhow!("Unable to hash {:?}", path))?;
+        let blob_path = self.blob_path(&blob);
+
+        if blob_path.exists() {
+            self.check_contents(&blob_path, path, &blob)?;
+            fs::remove_file(path)?;
+            link_or_copy(&blob_path, path)?;
+        } else {
+            fs::create_dir_all(blob_path.parent().unwrap_or(&self.root))?;
+            link_or_copy(path, &blob_path)?;
+        }
+
+        Ok(blob)
+    }
+
+    /// Fails with a `BlobCollision` if `path` does not have the same contents as the stored blob
+    fn check_contents(&self, blob_path: &Path, path: &Path, blob: &str) -> anyhow::Result<()> {
+        if !same_contents(blob_path, path)? {
+            return Err(BlobCollision {
+                blob: blob.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Adds one reference to each of the given blobs
+    pub fn add_references(&self, db: &Connection, blobs: &[(&str, u64)]) -> anyhow::Result<()> {
+        let mut statement = db.prepare(
+            "INSERT INTO cache_blobs (blob, size, refs) VALUES (?1, ?2, 1)
+                ON CONFLICT (blob) DO UPDATE SET refs = refs + 1",
+        )?;
+        for (blob, size) in blobs {
+            statement.execute(params![blob, size])?;
+        }
+        Ok(())
+    }
+
+    /// Releases one reference to each of the given blobs, and returns the ones which are no longer referenced.
+    /// Their files are left in place until they are removed with `remove_blobs`.
+    pub fn release_references(
+        &self,
+        db: &Connection,
+        blobs: &[(&str, u64)],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut unreferenced = vec![];
+        for (blob, _) in blobs {
+            let removed = db
+                .query_row(
+                    "DELETE FROM cache_blobs WHERE blob = ?1 AND refs <= 1 RETURNING size",
+                    params![blob],
+                    |row| row.get::<_, u64>(0),
+                )
+                .optional()?
+                .is_some();
+
+            if removed {
+                unreferenced.push(blob.to_string());
+            } else {
+                db.execute(
+                    "UPDATE cache_blobs SET refs = refs - 1 WHERE blob = ?1",
+                    params![blob],
+                )?;
+            }
+        }
+        Ok(unreferenced)
+    }
+
+    /// Removes the files of blobs which are no longer referenced. Returns the number of bytes freed.
+    pub fn remove_blobs(&self, blobs: &[String]) -> u64 {
+        let mut freed = 0;
+        for blob in blobs {
+            let blob_path = self.blob_path(blob);
+            trace!("Removing unreferenced blob {:?}", &blob_path);
+            if let Ok(metadata) = fs::metadata(&blob_path) {
+                freed += metadata.len();
+            }
+            fs::remove_file(&blob_path).ok();
+        }
+        freed
+    }
+
+    /// Returns the reference counts of every blob which has a record
+    pub fn references(&self, db: &NxDbConnection) -> anyhow::Result<HashMap<String, u32>> {
+        let references = db
+            .prepare("SELECT blob, refs FROM cache_blobs")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(references)
+    }
+
+    /// Overwrites the reference count of a blob, removing its record when it is no longer referenced
+    pub fn set_references(
+        &self,
+        db: &NxDbConnection,
+        blob: &str,
+        size: u64,
+        refs: u32,
+    ) -> anyhow::Result<()> {
+        if refs == 0 {
+            db.execute("DELETE FROM cache_blobs WHERE blob = ?1", params![blob])?;
+        } else {
+            db.execute(
+                "INSERT INTO cache_blobs (blob, size, refs) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (blob) DO UPDATE SET refs = ?3",
+                params![blob, size, refs],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the names of every file in the store, including leftover temporary files
+    pub fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut blobs = vec![];
+        for prefix in fs::read_dir(&self.root)? {
+            let prefix = prefix?;
+            if !prefix.file_type()?.is_dir() {
+                continue;
+            }
+            for blob in fs::read_dir(prefix.path())? {
+                blobs.push(blob?.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(blobs)
+    }
+
+    /// Removes a file listed by `list`
+    pub fn remove(&self, name: &str) {
+        let path = self.blob_path(name);
+        trace!("Removing blob {:?}", &path);
+        fs::remove_file(&path).ok();
+    }
+}
+
+/// Copies `src` to `dest` with the given strategy, falling back to a plain copy
+/// when the strategy is not supported between the two paths
+pub fn copy_file(src: &Path, dest: &Path, strategy: CopyStrategy) -> std::io::Result<()> {
+    match strategy {
+        CopyStrategy::Copy => fs::copy(src, dest).map(|_| ()),
+        CopyStrategy::Reflink => reflink_copy::reflink_or_copy(src, dest).map(|_| ()),
+        CopyStrategy::Hardlink => link_or_copy(src, dest),
+    }
+}
+
+pub fn make_read_only(path: &Path) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions)
+}
+
+fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (a, b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let (mut a, mut b) = (BufReader::new(a), BufReader::new(b));
+    let (mut a_buffer, mut b_buffer) = ([0; 8192], [0; 8192]);
+    loop {
+        let read = a.read(&mut a_buffer)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut b_buffer[..read])?;
+        if a_buffer[..read] != b_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+fn link_or_copy(src: &Path, dest: &Path) -> std::io::Result<()> {
+    if fs::hard_link(src, dest).is_err() {
+        trace!("Unable to hardlink {:?}, copying instead", dest);
+        fs::copy(src, dest)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn should_share_blobs_with_the_same_contents() {
+        let temp = TempDir::new().unwrap();
+        let store = BlobStore::new(temp.path()).unwrap();
+        temp.child("a.txt").write_str("contents").unwrap();
+        temp.child("b.txt").write_str("contents").unwrap();
+
+        store
+            .insert(
+                &temp.path().join("a.txt"),
+                "123",
+                &temp.path().join("a"),
+                CopyStrategy::Copy,
+            )
+            .unwrap();
+        store
+            .insert(
+                &temp.path().join("b.txt"),
+                "123",
+                &temp.path().join("b"),
+                CopyStrategy::Copy,
+            )
+            .unwrap();
+
+        assert_eq!(store.list().unwrap(), vec!["123"]);
+        temp.child("b").assert("contents");
+    }
+
+    #[test]
+    fn should_not_share_blobs_with_different_contents() {
+        let temp = TempDir::new().unwrap();
+        let store = BlobStore::new(temp.path()).unwrap();
+        temp.child("a.txt").write_str("contents").unwrap();
+        temp.child("b.txt").write_str("collides").unwrap();
+        store
+            .insert(
+                &temp.path().join("a.txt"),
+                "123",
+                &temp.path().join("a"),
+                CopyStrategy::Copy,
+            )
+            .unwrap();
+
+        let error = store
+            .insert(
+                &temp.path().join("b.txt"),
+                "123",
+                &temp.path().join("b"),
+                CopyStrategy::Copy,
+            )
+            .unwrap_err();
+        assert!(error.is::<BlobCollision>());
+        assert!(!temp.path().join("b").exists());
+        temp.child("blobs/12/123").assert("contents");
+    }
+}
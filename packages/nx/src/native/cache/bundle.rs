@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs::{rename, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::anyhow;
+use rkyv::{Archive, Deserialize, Infallible, Serialize};
+use tracing::trace;
+
+use crate::native::cache::archive::get_archive_path;
+
+/// Name of the index stored at the start of every bundle
+const BUNDLE_INDEX_FILE: &str = ".nx-bundle-index";
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[archive(check_bytes)]
+pub struct BundleTaskDetails {
+    pub project: String,
+    pub target: String,
+    pub configuration: Option<String>,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[archive(check_bytes)]
+pub struct BundleEntry {
+    pub hash: String,
+    pub code: i16,
+    pub task_details: Option<BundleTaskDetails>,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[archive(check_bytes)]
+pub struct BundleIndex {
+    pub entries: Vec<BundleEntry>,
+}
+
+/// Writes a bundle holding the index of the given entries followed by their archives
+pub fn write_bundle(bundle_path: &Path, entries: &[(BundleEntry, PathBuf)]) -> anyhow::Result<()> {
+    trace!("Writing cache bundle: {:?}", bundle_path);
+    let tmp_path = bundle_path.with_extension(format!("{}.tmp", process::id()));
+
+    // Archives are already compressed, so the bundle itself is not
+    let mut builder = tar::Builder::new(BufWriter::new(File::create(&tmp_path)?));
+
+    let index = BundleIndex {
+        entries: entries.iter().map(|(entry, _)| entry.clone()).collect(),
+    };
+    let index_bytes = rkyv::to_bytes::<_, 2048>(&index)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(index_bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, BUNDLE_INDEX_FILE, index_bytes.as_slice())?;
+
+    for (entry, archive_path) in entries {
+        builder
+            .append_path_with_name(archive_path, get_archive_path(Path::new(""), &entry.hash))?;
+    }
+
+    builder.into_inner()?.flush()?;
+    rename(&tmp_path, bundle_path)?;
+    Ok(())
+}
+
+/// Extracts the archives of a bundle into `cache_path`.
+/// `should_import` is called for every entry of the bundle before its archive is written.
+/// Returns the entries which were imported.
+pub fn read_bundle<F>(
+    bundle_path: &Path,
+    cache_path: &Path,
+    mut should_import: F,
+) -> anyhow::Result<Vec<BundleEntry>>
+where
+    F: FnMut(&BundleEntry) -> anyhow::Result<bool>,
+{
+    trace!("Reading cache bundle: {:?}", bundle_path);
+    let mut bundle = tar::Archive::new(BufReader::new(File::open(bundle_path)?));
+    let mut entries = bundle.entries()?;
+
+    let mut index_file = entries
+        .next()
+        .ok_or_else(|| anyhow!("{:?} is empty", bundle_path))??;
+    if index_file.path()? != Path::new(BUNDLE_INDEX_FILE) {
+        return Err(anyhow!("{:?} is not a cache bundle", bundle_path));
+    }
+    let mut index_bytes = vec![];
+    index_file.read_to_end(&mut index_bytes)?;
+    let index = index_from_bytes(&index_bytes)?;
+    // Hashes become file names in the cache, so anything else could escape it
+    if let Some(entry) = index
+        .entries
+        .iter()
+        .find(|entry| !is_valid_hash(&entry.hash))
+    {
+        return Err(anyhow!(
+            "{:?} contains an invalid hash: {:?}",
+            bundle_path,
+            entry.hash
+        ));
+    }
+
+    let mut pending = index
+        .entries
+        .into_iter()
+        .map(|entry| (get_archive_path(Path::new(""), &entry.hash), entry))
+        .collect::<HashMap<_, _>>();
+    let mut imported = vec![];
+
+    for file in entries {
+        let mut file = file?;
+        let Some(entry) = pending.remove(file.path()?.as_ref()) else {
+            continue;
+        };
+        if !should_import(&entry)? {
+            continue;
+        }
+
+        let archive_path = get_archive_path(cache_path, &entry.hash);
+        let tmp_path = archive_path.with_extension(format!("{}.tmp", process::id()));
+        file.unpack(&tmp_path)?;
+        rename(&tmp_path, &archive_path)?;
+        imported.push(entry);
+    }
+
+    if !pending.is_empty() {
+        trace!(
+            "{:?} is missing archives for {:?}",
+            bundle_path,
+            pending
+                .values()
+                .map(|entry| &entry.hash)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    Ok(imported)
+}
+
+fn is_valid_hash(hash: &str) -> bool {
+    !hash.is_empty() && hash.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn index_from_bytes(bytes: &[u8]) -> anyhow::Result<BundleIndex> {
+    let archived = rkyv::check_archived_root::<BundleIndex>(bytes)
+        .map_err(|_| anyhow!("invalid cache bundle index"))?;
+    <ArchivedBundleIndex as Deserialize<BundleIndex, Infallible>>::deserialize(
+        archived,
+        &mut rkyv::Infallible,
+    )
+    .map_err(anyhow::Error::from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    fn entry(hash: &str) -> BundleEntry {
+        BundleEntry {
+            hash: hash.into(),
+            code: 0,
+            task_details: Some(BundleTaskDetails {
+                project: "proj".into(),
+                target: "build".into(),
+                configuration: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn should_round_trip_bundles() {
+        let temp = TempDir::new().unwrap();
+        temp.child("source/123.tar.zst")
+            .write_str("archive 123")
+            .unwrap();
+        temp.child("source/234.tar.zst")
+            .write_str("archive 234")
+            .unwrap();
+        temp.child("cache").create_dir_all().unwrap();
+        let bundle_path = temp.path().join("bundle.tar");
+
+        write_bundle(
+            &bundle_path,
+            &[
+                (entry("123"), temp.path().join("source/123.tar.zst")),
+                (entry("234"), temp.path().join("source/234.tar.zst")),
+            ],
+        )
+        .unwrap();
+
+        let imported = read_bundle(&bundle_path, &temp.path().join("cache"), |entry| {
+            Ok(entry.hash != "234")
+        })
+        .unwrap();
+
+        assert_eq!(imported, vec![entry("123")]);
+        temp.child("cache/123.tar.zst").assert("archive 123");
+        assert!(!temp.path().join("cache/234.tar.zst").exists());
+    }
+
+    #[test]
+    fn should_reject_invalid_hashes() {
+        let temp = TempDir::new().unwrap();
+        temp.child("cache").create_dir_all().unwrap();
+        let bundle_path = temp.path().join("bundle.tar");
+
+        let index = BundleIndex {
+            entries: vec![entry("../outside")],
+        };
+        let index_bytes = rkyv::to_bytes::<_, 2048>(&index).unwrap();
+        let mut builder = tar::Builder::new(File::create(&bundle_path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(index_bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, BUNDLE_INDEX_FILE, index_bytes.as_slice())
+            .unwrap();
+        builder.into_inner().unwrap().flush().unwrap();
+
+        let mut called = false;
+        assert!(read_bundle(&bundle_path, &temp.path().join("cache"), |_| {
+            called = true;
+            Ok(true)
+        })
+        .is_err());
+        assert!(!called);
+    }
+
+    #[test]
+    fn should_reject_other_files() {
+        let temp = TempDir::new().unwrap();
+        temp.child("not-a-bundle").write_str("contents").unwrap();
+
+        assert!(read_bundle(&temp.path().join("not-a-bundle"), temp.path(), |_| Ok(true)).is_err());
+    }
+}
@@ -1,16 +1,41 @@
-use std::fs::{create_dir_all, read_to_string, write};
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, read, read_link, read_to_string, rename, write};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use fs_extra::remove_items;
 use napi::bindgen_prelude::*;
+use rayon::prelude::*;
 use regex::Regex;
-use rusqlite::params;
-use tracing::trace;
+use rusqlite::vtab::array;
+use rusqlite::{params, types::Value, Connection, OptionalExtension};
+use tracing::{trace, warn};
+use walkdir::WalkDir;
 
-use crate::native::cache::expand_outputs::_expand_outputs;
-use crate::native::cache::file_ops::_copy;
+use crate::native::cache::archive::{
+    extract_archive, get_archive_path, read_archive_result, verify_archive, write_archive,
+};
+use crate::native::cache::blob_store::{copy_file, make_read_only, BlobCollision, BlobStore};
+use crate::native::cache::bundle::{read_bundle, write_bundle, BundleEntry, BundleTaskDetails};
+use crate::native::cache::encryption::CacheCipher;
+use crate::native::cache::expand_outputs::{_expand_outputs, _expand_outputs_in_paths};
+use crate::native::cache::file_ops::{_copy, symlink};
+use crate::native::cache::fsck::{read_cache_dir, CacheFsckReport};
+use crate::native::cache::manifest::{
+    apply_metadata, file_mode, file_mtime, read_manifest, write_manifest, CacheManifest,
+    ManifestEntry, ManifestEntryKind, MANIFEST_FILE,
+};
+use crate::native::cache::pins::{
+    get_entries_by_tag, pin_entry, unpin_entries_by_tag, unpin_entry, TaggedCacheEntry,
+};
+use crate::native::cache::staging::{staging_path, sweep_staging, EntryLock};
+use crate::native::cache::stats::{get_cache_stats, CacheStats, CacheStatsOptions};
+use crate::native::cache::storage::http::{HttpCacheStorage, HttpCacheStorageOptions};
+use crate::native::cache::storage::local::LocalFsStorage;
+use crate::native::cache::storage::CacheStorage;
 use crate::native::db::connection::NxDbConnection;
+use crate::native::hasher::hash_file_path;
 use crate::native::utils::Normalize;
 
 #[napi(object)]
@@ -21,6 +46,163 @@ pub struct CachedResult {
     pub outputs_path: String,
 }
 
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct CacheEvictionPolicy {
+    /// The maximum total size of the cache in bytes
+    pub max_cache_size: Option<i64>,
+    /// The maximum number of entries in the cache
+    pub max_entries: Option<u32>,
+    /// The maximum number of days since an entry was last accessed
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for CacheEvictionPolicy {
+    fn default() -> Self {
+        Self {
+            max_cache_size: None,
+            max_entries: None,
+            max_age_days: Some(7),
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct CacheEvictionReport {
+    pub entries_removed: u32,
+    pub bytes_freed: i64,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct CacheExportFilter {
+    /// Only export entries of tasks of these projects
+    pub projects: Option<Vec<String>>,
+    /// Only export entries of tasks of these targets
+    pub targets: Option<Vec<String>>,
+    /// Only export entries which were created in the last number of days
+    pub max_age_days: Option<u32>,
+    /// Only export these entries
+    pub hashes: Option<Vec<String>>,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Default, PartialEq)]
+pub enum CacheEntryFormat {
+    /// Entries are stored as directories which share their files through the blob store
+    #[default]
+    Directory,
+    /// Entries are stored as single compressed archives
+    Archive,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct CacheRestoreReport {
+    /// Paths which were written to the workspace
+    pub written: Vec<String>,
+    /// Paths which were removed from the workspace
+    pub removed: Vec<String>,
+    /// The number of files which already matched the cache and were left untouched
+    pub unchanged: u32,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Default, PartialEq)]
+pub enum RestoreMode {
+    /// The outputs are removed and restored completely
+    #[default]
+    Replace,
+    /// Only the files which differ from the cache are rewritten,
+    /// and only the files which are not in the cache are removed
+    Differential,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Default, PartialEq)]
+pub enum CopyStrategy {
+    /// Files are copied
+    #[default]
+    Copy,
+    /// Files are cloned with copy-on-write when the filesystem supports it, and copied otherwise
+    Reflink,
+    /// Files are hardlinked to the cache, which makes restored outputs read-only.
+    /// Linked files are shared by every entry with the same contents,
+    /// so the mode and modification time of each entry are not restored.
+    Hardlink,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Default, PartialEq)]
+pub enum CacheAccessMode {
+    /// Entries are read from and written to the cache
+    #[default]
+    ReadWrite,
+    /// Entries are only read from the cache. Storing entries does nothing.
+    ReadOnly,
+    /// Entries are only written to the cache. Reading entries always misses.
+    WriteOnly,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct NxCacheOptions {
+    /// How new entries are written to the cache directory.
+    /// Entries are always read in the format they were written in.
+    pub entry_format: Option<CacheEntryFormat>,
+    /// How files are copied between the workspace and entries stored as directories.
+    /// Entries stored as archives are always extracted.
+    pub copy_strategy: Option<CopyStrategy>,
+    /// How outputs are restored into the workspace
+    pub restore_mode: Option<RestoreMode>,
+    /// Check the files of entries against the hashes recorded when they were stored.
+    /// Entries which fail the check are treated as a miss and removed.
+    pub verify_integrity: Option<bool>,
+    /// Share entries with a remote cache through `retrieveFromRemote` and `storeToRemote`
+    pub remote_cache: Option<RemoteCacheOptions>,
+    /// Whether entries are read from and written to the cache, locally and remotely.
+    /// Defaults to the value of `NX_CACHE_ACCESS_MODE` (`read-write`, `read-only` or `write-only`).
+    pub access_mode: Option<CacheAccessMode>,
+    /// Directories which are looked up in order when an entry is missing from the cache directory.
+    /// Entries found in a layer are copied into the cache directory, and new entries are copied into every writable layer.
+    pub layers: Option<Vec<CacheLayerOptions>>,
+    /// Encrypt terminal outputs and archives with the key in `NX_CLOUD_ENCRYPTION_KEY`.
+    /// Entries which cannot be decrypted with the key are treated as a miss.
+    /// New entries are always stored as archives, since the files of directories cannot be encrypted.
+    pub encrypt: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct CacheLayerOptions {
+    /// A directory holding entries as archives, such as a network drive or a directory baked into a CI image
+    pub path: String,
+    /// Entries are only read from read-only layers. Defaults to false.
+    pub read_only: Option<bool>,
+}
+
+struct CacheLayer {
+    path: String,
+    read_only: bool,
+    storage: LocalFsStorage,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct RemoteCacheOptions {
+    /// The url of an HTTP cache server, or the path of a directory shared between machines
+    pub url: String,
+    /// Sent as a bearer token to HTTP cache servers
+    pub token: Option<String>,
+    /// How many times a failed request is retried. Defaults to 3.
+    pub max_retries: Option<u32>,
+    /// How many requests can be in flight at the same time. Defaults to 8.
+    pub max_concurrency: Option<u32>,
+    /// How many seconds a request can wait for the HTTP cache server before it fails. Defaults to 30.
+    pub timeout_secs: Option<u32>,
+}
+
 #[napi]
 pub struct NxCache {
     pub cache_directory: String,
@@ -28,6 +210,15 @@ pub struct NxCache {
     cache_path: PathBuf,
     db: External<NxDbConnection>,
     link_task_details: bool,
+    blob_store: BlobStore,
+    entry_format: CacheEntryFormat,
+    copy_strategy: CopyStrategy,
+    restore_mode: RestoreMode,
+    verify_integrity: bool,
+    remote_cache: Option<Box<dyn CacheStorage>>,
+    access_mode: CacheAccessMode,
+    layers: Vec<CacheLayer>,
+    cipher: Option<CacheCipher>,
 }
 
 #[napi]
@@ -38,18 +229,55 @@ impl NxCache {
         cache_path: String,
         db_connection: External<NxDbConnection>,
         link_task_details: Option<bool>,
+        options: Option<NxCacheOptions>,
     ) -> anyhow::Result<Self> {
+        let options = options.unwrap_or_default();
         let cache_path = PathBuf::from(&cache_path);
+        let access_mode = if db_connection.is_read_only() {
+            // Nothing can be recorded in a read-only database
+            CacheAccessMode::ReadOnly
+        } else {
+            options
+                .access_mode
+                .or_else(access_mode_from_env)
+                .unwrap_or_default()
+        };
+        trace!("Cache access mode: {:?}", &access_mode);
+        let cipher = if options.encrypt.unwrap_or(false) {
+            Some(CacheCipher::from_env()?)
+        } else {
+            None
+        };
+        let entry_format = if cipher.is_some() {
+            CacheEntryFormat::Archive
+        } else {
+            options.entry_format.unwrap_or_default()
+        };
 
         create_dir_all(&cache_path)?;
         create_dir_all(cache_path.join("terminalOutputs"))?;
+        sweep_staging(&cache_path)?;
 
         let r = Self {
             db: db_connection,
             workspace_root: PathBuf::from(workspace_root),
             cache_directory: cache_path.to_normalized_string(),
+            blob_store: BlobStore::new(&cache_path)?,
             cache_path,
             link_task_details: link_task_details.unwrap_or(true),
+            entry_format,
+            copy_strategy: options.copy_strategy.unwrap_or_default(),
+            restore_mode: options.restore_mode.unwrap_or_default(),
+            verify_integrity: options.verify_integrity.unwrap_or(false),
+            remote_cache: options.remote_cache.map(create_remote_cache).transpose()?,
+            access_mode,
+            layers: options
+                .layers
+                .unwrap_or_default()
+                .into_iter()
+                .map(create_cache_layer)
+                .collect::<anyhow::Result<_>>()?,
+            cipher,
         };
 
         r.setup()?;
@@ -64,6 +292,9 @@ impl NxCache {
                     code   INTEGER NOT NULL,
                     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                     accessed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    size INTEGER NOT NULL DEFAULT 0,
+                    pinned BOOLEAN NOT NULL DEFAULT 0,
+                    tags TEXT,
                     FOREIGN KEY (hash) REFERENCES task_details (hash)
               );
             "
@@ -72,12 +303,17 @@ impl NxCache {
                     hash    TEXT PRIMARY KEY NOT NULL,
                     code   INTEGER NOT NULL,
                     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                    accessed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                    accessed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    size INTEGER NOT NULL DEFAULT 0,
+                    pinned BOOLEAN NOT NULL DEFAULT 0,
+                    tags TEXT
                 );
                 "
         };
 
-        self.db.execute(query, []).map_err(anyhow::Error::from)?;
+        array::load_module(&self.db.conn)?;
+        self.db.create_table("cache_outputs", query)?;
+        self.blob_store.setup(&self.db)?;
         Ok(())
     }
 
@@ -85,38 +321,230 @@ impl NxCache {
     pub fn get(&mut self, hash: String) -> anyhow::Result<Option<CachedResult>> {
         let start = Instant::now();
         trace!("GET {}", &hash);
-        let task_dir = self.cache_path.join(&hash);
-
-        let terminal_output_path = self.get_task_outputs_path_internal(&hash);
-
-        let r = self
+        if self.access_mode == CacheAccessMode::WriteOnly {
+            trace!("GET {} skipped, the cache is write-only", &hash);
+            return Ok(None);
+        }
+        // Read-only databases cannot record when entries are accessed
+        let query = if self.db.is_read_only() {
+            "SELECT code FROM cache_outputs WHERE hash = ?1"
+        } else {
+            "UPDATE cache_outputs
+                SET accessed_at = CURRENT_TIMESTAMP
+                WHERE hash = ?1
+                RETURNING code"
+        };
+        let code = self
             .db
-            .query_row(
-                "UPDATE cache_outputs
-                    SET accessed_at = CURRENT_TIMESTAMP
-                    WHERE hash = ?1
-                    RETURNING code",
-                params![hash],
-                |row| {
-                    let code: i16 = row.get(0)?;
-
-                    let start = Instant::now();
-                    let terminal_output =
-                        read_to_string(terminal_output_path).unwrap_or(String::from(""));
-                    trace!("TIME reading terminal outputs {:?}", start.elapsed());
-
-                    Ok(CachedResult {
-                        code,
-                        terminal_output,
-                        outputs_path: task_dir.to_normalized_string(),
-                    })
-                },
-            )
+            .query_row(query, params![hash], |row| row.get::<_, i16>(0))
             .map_err(|e| anyhow::anyhow!("Unable to get {}: {:?}", &hash, e))?;
+
+        let r = match code {
+            Some(code) => self.read_entry(&hash, code, true)?,
+            None => self.get_missing_entry(&hash)?,
+        };
+
         trace!("GET {} {:?}", &hash, start.elapsed());
         Ok(r)
     }
 
+    /// Looks up many entries with a single query. Returns the results of the entries which were found.
+    /// Terminal outputs are only read with `include_terminal_output`, and are empty otherwise.
+    /// Without them, entries which cannot be decrypted are only detected when they are restored.
+    #[napi]
+    pub fn get_many(
+        &mut self,
+        hashes: Vec<String>,
+        include_terminal_output: Option<bool>,
+    ) -> anyhow::Result<HashMap<String, CachedResult>> {
+        let start = Instant::now();
+        trace!("GET {} entries", hashes.len());
+        if self.access_mode == CacheAccessMode::WriteOnly {
+            trace!("GET skipped, the cache is write-only");
+            return Ok(HashMap::new());
+        }
+        let include_terminal_output = include_terminal_output.unwrap_or(false);
+
+        let values = Rc::new(
+            hashes
+                .iter()
+                .map(|hash| Value::from(hash.clone()))
+                .collect::<Vec<Value>>(),
+        );
+        let query = if self.db.is_read_only() {
+            "SELECT hash, code FROM cache_outputs WHERE hash IN rarray(?1)"
+        } else {
+            "UPDATE cache_outputs
+                SET accessed_at = CURRENT_TIMESTAMP
+                WHERE hash IN rarray(?1)
+                RETURNING hash, code"
+        };
+        let codes = self
+            .db
+            .prepare(query)?
+            .query_map([values], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<HashMap<String, i16>>>()?;
+
+        let mut results = HashMap::with_capacity(hashes.len());
+        for hash in hashes {
+            let r = match codes.get(&hash) {
+                Some(code) => self.read_entry(&hash, *code, include_terminal_output)?,
+                None => self.get_missing_entry(&hash)?,
+            };
+            if let Some(r) = r {
+                results.insert(hash, r);
+            }
+        }
+
+        trace!("GET {} entries {:?}", results.len(), start.elapsed());
+        Ok(results)
+    }
+
+    /// Returns the given hashes which are in the cache or in one of its layers,
+    /// without reading the entries or marking them as accessed
+    #[napi]
+    pub fn contains_many(&self, hashes: Vec<String>) -> anyhow::Result<Vec<String>> {
+        if self.access_mode == CacheAccessMode::WriteOnly {
+            return Ok(vec![]);
+        }
+        let local_hashes = self.get_local_hashes(&hashes)?;
+        Ok(hashes
+            .into_iter()
+            .filter(|hash| {
+                local_hashes.contains(hash)
+                    || self
+                        .layers
+                        .iter()
+                        .any(|layer| layer.storage.exists(hash).unwrap_or(false))
+            })
+            .collect())
+    }
+
+    /// Builds the result of an entry which has a record, reading its terminal output if asked to.
+    /// Entries which cannot be decrypted or fail the integrity check are a miss.
+    fn read_entry(
+        &self,
+        hash: &str,
+        code: i16,
+        include_terminal_output: bool,
+    ) -> anyhow::Result<Option<CachedResult>> {
+        let task_dir = self.cache_path.join(hash);
+        let archive_path = get_archive_path(&self.cache_path, hash);
+        let is_archive = archive_path.exists();
+
+        let terminal_output = if include_terminal_output {
+            let start = Instant::now();
+            let terminal_output = if is_archive {
+                read_archive_result(&archive_path, self.cipher.as_ref())
+                    .map(|(_, terminal_output)| terminal_output)
+            } else {
+                self.read_terminal_output(hash)
+            };
+            trace!("TIME reading terminal outputs {:?}", start.elapsed());
+            match terminal_output {
+                Ok(terminal_output) => terminal_output,
+                Err(e) if self.cipher.is_some() => {
+                    warn!(
+                        "Cache entry {} could not be decrypted and is treated as a miss: {:?}",
+                        hash, e
+                    );
+                    return Ok(None);
+                }
+                Err(_) => String::new(),
+            }
+        } else {
+            String::new()
+        };
+
+        if self.verify_integrity && !self.verify_entry(&task_dir, &archive_path) {
+            if self.db.is_read_only() {
+                warn!("Cache entry {} is corrupted and will be ignored", hash);
+            } else {
+                warn!("Cache entry {} is corrupted and will be removed", hash);
+                self.remove_entries(&[(hash.to_string(), 0)])?;
+            }
+            return Ok(None);
+        }
+
+        Ok(Some(CachedResult {
+            code,
+            terminal_output,
+            outputs_path: if is_archive {
+                archive_path.to_normalized_string()
+            } else {
+                task_dir.to_normalized_string()
+            },
+        }))
+    }
+
+    fn get_missing_entry(&self, hash: &str) -> anyhow::Result<Option<CachedResult>> {
+        if self.db.is_read_only() {
+            // Entries found in the layers could not be recorded
+            return Ok(None);
+        }
+        // Leftover files without a record are reported as a miss and left to `fsck`
+        self.get_from_layers(hash)
+    }
+
+    /// Looks up a missing entry in the cache layers in order,
+    /// and promotes the first one found into the cache directory.
+    /// A read-only cache retrieves it into the staging directory without recording it instead.
+    fn get_from_layers(&self, hash: &str) -> anyhow::Result<Option<CachedResult>> {
+        let promote = self.access_mode != CacheAccessMode::ReadOnly;
+        let archive_path = if promote {
+            get_archive_path(&self.cache_path, hash)
+        } else {
+            staging_path(&self.cache_path, hash)?.with_extension("tar.zst")
+        };
+        for layer in self.layers.iter() {
+            let retrieved = layer
+                .storage
+                .retrieve(hash, &archive_path)
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Unable to read {} from the cache layer {}: {:?}",
+                        hash, &layer.path, e
+                    );
+                    false
+                });
+            if !retrieved {
+                continue;
+            }
+            if self.verify_integrity && !self.verify_archive(&archive_path) {
+                warn!(
+                    "{} from the cache layer {} is corrupted and will be ignored",
+                    hash, &layer.path
+                );
+                remove_items(&[&archive_path])?;
+                continue;
+            }
+            let (code, terminal_output) =
+                match read_archive_result(&archive_path, self.cipher.as_ref()) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        warn!(
+                            "{} from the cache layer {} is unreadable and will be ignored: {:?}",
+                            hash, &layer.path, e
+                        );
+                        remove_items(&[&archive_path])?;
+                        continue;
+                    }
+                };
+
+            if promote {
+                trace!("Promoting {} from the cache layer {}", hash, &layer.path);
+                let size = std::fs::metadata(&archive_path)?.len();
+                self.record_to_cache(&[(hash.to_string(), code, size)])?;
+            }
+            return Ok(Some(CachedResult {
+                code,
+                terminal_output,
+                outputs_path: archive_path.to_normalized_string(),
+            }));
+        }
+        Ok(None)
+    }
+
     #[napi]
     pub fn put(
         &mut self,
@@ -126,35 +554,259 @@ impl NxCache {
         code: i16,
     ) -> anyhow::Result<()> {
         trace!("PUT {}", &hash);
+        if self.access_mode == CacheAccessMode::ReadOnly {
+            trace!("PUT {} skipped, the cache is read-only", &hash);
+            return Ok(());
+        }
         let task_dir = self.cache_path.join(&hash);
+        let archive_path = get_archive_path(&self.cache_path, &hash);
+
+        // Expand the outputs
+        let expanded_outputs = _expand_outputs(&self.workspace_root, outputs)?;
+        let manifest = self.collect_manifest(&expanded_outputs)?;
+
+        // The entry is written to a staging path and moved into place once it is complete,
+        // so that an interrupted put never leaves a partial entry behind.
+        // Puts of the same hash wait for each other.
+        let _lock = EntryLock::acquire(&self.cache_path, &hash)?;
+        let staging_path = staging_path(&self.cache_path, &hash)?;
+        let (published_path, size, blobs) = match self.entry_format {
+            CacheEntryFormat::Directory => {
+                let size = match self.stage_directory(&staging_path, &terminal_output, &manifest) {
+                    Ok(size) => size,
+                    Err(e) if e.is::<BlobCollision>() => {
+                        warn!("{} will not be cached: {}", &hash, e);
+                        remove_items(&[&staging_path, &staging_path.with_extension("terminal")])?;
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                };
+                (&task_dir, size, manifest.blobs())
+            }
+            CacheEntryFormat::Archive => {
+                let size = write_archive(
+                    &staging_path,
+                    &self.workspace_root,
+                    &manifest,
+                    code,
+                    &terminal_output,
+                    self.cipher.as_ref(),
+                )?;
+                (&archive_path, size, vec![])
+            }
+        };
+
+        // Move the previous entry aside, so that its manifest no longer describes the published entry
+        let previous_manifest = self.entry_manifest(&hash);
+        let mut previous_paths = vec![];
+        for (i, path) in [&task_dir, &archive_path].into_iter().enumerate() {
+            if path.symlink_metadata().is_ok() {
+                let previous_path = staging_path.with_extension(format!("previous{}", i));
+                rename(path, &previous_path)?;
+                previous_paths.push(previous_path);
+            }
+        }
+
+        // Being interrupted before the entry is published leaves a record without files,
+        // which leaks references until `fsck` recounts them but never releases a blob twice.
+        let unreferenced_blobs = self.db.transaction(|tx| {
+            self.replace_record(tx, &hash, code, size, &blobs, previous_manifest.as_ref())
+        })?;
+
+        trace!("Publishing {:?} -> {:?}", &staging_path, published_path);
+        rename(&staging_path, published_path)?;
+        if self.entry_format == CacheEntryFormat::Directory {
+            rename(
+                staging_path.with_extension("terminal"),
+                self.get_task_outputs_path_internal(&hash),
+            )?;
+        }
+
+        self.blob_store.remove_blobs(&unreferenced_blobs);
+        remove_items(&previous_paths)?;
+        self.store_to_layers(&hash, code)?;
+        Ok(())
+    }
+
+    /// Copies an entry into every writable cache layer
+    fn store_to_layers(&self, hash: &str, code: i16) -> anyhow::Result<()> {
+        let writable_layers = self
+            .layers
+            .iter()
+            .filter(|layer| !layer.read_only)
+            .collect::<Vec<_>>();
+        if writable_layers.is_empty() {
+            return Ok(());
+        }
+
+        let mut temporary_archives = vec![];
+        if let Some(archive_path) = self.archive_entry(hash, code, &mut temporary_archives)? {
+            for layer in writable_layers {
+                trace!("Storing {} in the cache layer {}", hash, &layer.path);
+                if let Err(e) = layer.storage.store(hash, &archive_path) {
+                    warn!(
+                        "Unable to store {} in the cache layer {}: {:?}",
+                        hash, &layer.path, e
+                    );
+                }
+            }
+        }
+        remove_items(&temporary_archives)?;
+        Ok(())
+    }
 
-        // Remove the task directory
-        //
-        trace!("Removing task directory: {:?}", &task_dir);
-        remove_items(&[&task_dir])?;
-        // Create the task directory again
-        trace!("Creating task directory: {:?}", &task_dir);
-        create_dir_all(&task_dir)?;
+    /// Writes the entry into `staging_path`, and its terminal output next to it
+    fn stage_directory(
+        &self,
+        staging_path: &Path,
+        terminal_output: &str,
+        manifest: &CacheManifest,
+    ) -> anyhow::Result<u64> {
+        trace!("Creating staging directory: {:?}", staging_path);
+        create_dir_all(staging_path)?;
 
         // Write the terminal outputs into a file
-        let task_outputs_path = self.get_task_outputs_path_internal(&hash);
-        trace!("Writing terminal outputs to: {:?}", &task_outputs_path);
-        write(task_outputs_path, terminal_output)?;
+        self.write_terminal_output(&staging_path.with_extension("terminal"), terminal_output)?;
 
-        // Expand the outputs
-        let expanded_outputs = _expand_outputs(&self.workspace_root, outputs)?;
+        // Store the outputs in the blob store and link them into the staging directory
+        for entry in manifest.entries.iter() {
+            let dest = staging_path.join(&entry.path);
+            if let Some(parent) = dest.parent() {
+                create_dir_all(parent)?;
+            }
+
+            match &entry.kind {
+                ManifestEntryKind::Directory => create_dir_all(&dest)?,
+                ManifestEntryKind::Symlink { target } => symlink(target, &dest)?,
+                ManifestEntryKind::File { blob, .. } => {
+                    let src = self.workspace_root.join(&entry.path);
+                    self.blob_store
+                        .insert(&src, blob, &dest, self.copy_strategy)?;
+                }
+            }
+        }
+        write_manifest(staging_path, manifest)?;
+
+        Ok(manifest.size() + terminal_output.len() as u64)
+    }
+
+    fn collect_manifest(&self, expanded_outputs: &[String]) -> anyhow::Result<CacheManifest> {
+        let mut manifest = CacheManifest::default();
+        let mut collected_paths = HashSet::new();
 
-        // Copy the outputs to the cache
         for expanded_output in expanded_outputs.iter() {
             let p = self.workspace_root.join(expanded_output);
-            if p.exists() {
-                let cached_outputs_dir = task_dir.join(expanded_output);
-                trace!("Copying {:?} -> {:?}", &p, &cached_outputs_dir);
-                _copy(p, cached_outputs_dir)?;
+            if !p.exists() {
+                continue;
+            }
+
+            trace!("Collecting outputs from {:?}", &p);
+            let entries = if p.is_symlink() {
+                vec![p]
+            } else {
+                WalkDir::new(&p)
+                    .into_iter()
+                    .map(|entry| entry.map(|e| e.into_path()))
+                    .collect::<std::result::Result<Vec<_>, _>>()?
+            };
+
+            for path in entries {
+                let normalized_path = path
+                    .strip_prefix(&self.workspace_root)?
+                    .to_normalized_string();
+                if !collected_paths.insert(normalized_path.clone()) {
+                    continue;
+                }
+
+                let metadata = path.symlink_metadata()?;
+                let kind = if metadata.is_dir() {
+                    ManifestEntryKind::Directory
+                } else if metadata.is_symlink() {
+                    ManifestEntryKind::Symlink {
+                        target: read_link(&path)?.to_normalized_string(),
+                    }
+                } else {
+                    ManifestEntryKind::File {
+                        blob: hash_file_path(&path)
+                            .ok_or_else(|| anyhow::anyhow!("Unable to hash {:?}", &path))?,
+                        size: metadata.len(),
+                    }
+                };
+
+                manifest.entries.push(ManifestEntry {
+                    path: normalized_path,
+                    mode: file_mode(&metadata),
+                    mtime: file_mtime(&metadata),
+                    kind,
+                });
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    fn verify_entry(&self, task_dir: &Path, archive_path: &Path) -> bool {
+        let start = Instant::now();
+        let valid = if archive_path.exists() {
+            self.verify_archive(archive_path)
+        } else if !task_dir.is_dir() {
+            trace!("{:?} does not exist", task_dir);
+            false
+        } else {
+            match read_manifest(task_dir) {
+                Some(manifest) => self.verify_manifest(task_dir, &manifest),
+                // Entries written by older versions of Nx do not have a manifest to check against
+                None => !task_dir.join(MANIFEST_FILE).exists(),
+            }
+        };
+        trace!("TIME verifying {:?} {:?}", task_dir, start.elapsed());
+        valid
+    }
+
+    fn verify_archive(&self, archive_path: &Path) -> bool {
+        verify_archive(archive_path, self.cipher.as_ref()).unwrap_or_else(|e| {
+            trace!("Unable to verify {:?}: {:?}", archive_path, e);
+            false
+        })
+    }
+
+    fn verify_manifest(&self, task_dir: &Path, manifest: &CacheManifest) -> bool {
+        manifest.entries.iter().all(|entry| {
+            let ManifestEntryKind::File { blob, .. } = &entry.kind else {
+                return true;
+            };
+            // Check the file which restoring the entry would copy
+            let blob_path = self.blob_store.blob_path(blob);
+            let src = if blob_path.exists() {
+                blob_path
+            } else {
+                task_dir.join(&entry.path)
+            };
+            let matches = hash_file_path(&src).is_some_and(|hash| &hash == blob);
+            if !matches {
+                trace!("{:?} does not match its hash {}", &src, blob);
             }
+            matches
+        })
+    }
+
+    /// Returns the manifest of a directory entry, which lists the blobs it references.
+    /// An archive takes precedence over a directory of the same hash, so the directory's
+    /// manifest is ignored when both exist.
+    fn entry_manifest(&self, hash: &str) -> Option<CacheManifest> {
+        if get_archive_path(&self.cache_path, hash).exists() {
+            return None;
         }
+        read_manifest(self.cache_path.join(hash))
+    }
 
-        self.record_to_cache(hash, code)?;
+    /// Removes the leftover directory of an entry which is not recorded.
+    /// Blobs are only referenced by recorded entries, so none are released.
+    fn remove_stale_entry(&self, hash: &str, task_dir: &Path) -> anyhow::Result<()> {
+        if task_dir.is_dir() {
+            trace!("Removing stale cache entry: {}", hash);
+            remove_items(&[task_dir])?;
+        }
         Ok(())
     }
 
@@ -169,31 +821,486 @@ impl NxCache {
             &hash,
             &result.outputs_path
         );
+        if self.access_mode == CacheAccessMode::ReadOnly {
+            trace!("Applying {} skipped, the cache is read-only", &hash);
+            return Ok(());
+        }
         let terminal_output = result.terminal_output;
-        write(self.get_task_outputs_path(hash.clone()), terminal_output)?;
+        let mut size = terminal_output.len() as u64;
+        self.write_terminal_output(
+            &self.get_task_outputs_path_internal(&hash),
+            &terminal_output,
+        )?;
+
+        // Move the downloaded files into the blob store so they are shared with other entries
+        let task_dir = self.cache_path.join(&hash);
+        let previous_manifest = self.entry_manifest(&hash);
+        let mut manifest = None;
+        if task_dir.is_dir() {
+            match self.adopt_entry(&task_dir) {
+                Ok(adopted_manifest) => {
+                    write_manifest(&task_dir, &adopted_manifest)?;
+                    size += adopted_manifest.size();
+                    manifest = Some(adopted_manifest);
+                }
+                // Without a manifest, the entry is kept as is and shares none of its files
+                Err(e) if e.is::<BlobCollision>() => {
+                    warn!("{} will not be shared with other entries: {}", &hash, e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
 
         let code: i16 = result.code;
-        self.record_to_cache(hash, code)?;
+        let blobs = manifest.as_ref().map(|m| m.blobs()).unwrap_or_default();
+        let unreferenced_blobs = self.db.transaction(|tx| {
+            self.replace_record(tx, &hash, code, size, &blobs, previous_manifest.as_ref())
+        })?;
+        self.blob_store.remove_blobs(&unreferenced_blobs);
         Ok(())
     }
 
+    /// Records an entry which references `blobs` in place of the previous record of its hash,
+    /// keeping the pin and tags of the previous record. The references of the previous entry
+    /// are only released if it was recorded, since only recorded entries hold any.
+    /// Returns the blobs which are no longer referenced.
+    fn replace_record(
+        &self,
+        tx: &Connection,
+        hash: &str,
+        code: i16,
+        size: u64,
+        blobs: &[(&str, u64)],
+        previous_manifest: Option<&CacheManifest>,
+    ) -> anyhow::Result<Vec<String>> {
+        let recorded = tx
+            .query_row(
+                "SELECT 1 FROM cache_outputs WHERE hash = ?1",
+                params![hash],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        // Blobs shared with the previous entry must not be released before they are referenced again
+        self.blob_store.add_references(tx, blobs)?;
+        let unreferenced_blobs = match (recorded, previous_manifest) {
+            (true, Some(previous_manifest)) => self
+                .blob_store
+                .release_references(tx, &previous_manifest.blobs())?,
+            _ => vec![],
+        };
+        record_entries(tx, &[(hash.to_string(), code, size)])?;
+        Ok(unreferenced_blobs)
+    }
+
+    fn adopt_entry(&self, task_dir: &Path) -> anyhow::Result<CacheManifest> {
+        let mut manifest = CacheManifest::default();
+
+        for entry in WalkDir::new(task_dir).min_depth(1) {
+            let entry = entry?;
+            let relative_path = entry.path().strip_prefix(task_dir)?;
+            if relative_path == Path::new(MANIFEST_FILE) {
+                continue;
+            }
+
+            let metadata = entry.path().symlink_metadata()?;
+            let kind = if metadata.is_dir() {
+                ManifestEntryKind::Directory
+            } else if metadata.is_symlink() {
+                ManifestEntryKind::Symlink {
+                    target: read_link(entry.path())?.to_normalized_string(),
+                }
+            } else {
+                ManifestEntryKind::File {
+                    blob: self.blob_store.adopt(entry.path())?,
+                    size: metadata.len(),
+                }
+            };
+
+            manifest.entries.push(ManifestEntry {
+                path: relative_path.to_normalized_string(),
+                mode: file_mode(&metadata),
+                mtime: file_mtime(&metadata),
+                kind,
+            });
+        }
+
+        Ok(manifest)
+    }
+
+    /// Downloads the entries which are missing from the local cache from the remote cache.
+    /// Returns the results of the entries which were found in the remote cache.
+    #[napi]
+    pub fn retrieve_from_remote(
+        &mut self,
+        hashes: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, CachedResult>> {
+        let Some(remote_cache) = &self.remote_cache else {
+            return Ok(HashMap::new());
+        };
+        if self.access_mode == CacheAccessMode::WriteOnly {
+            trace!("Retrieving from the remote cache skipped, the cache is write-only");
+            return Ok(HashMap::new());
+        }
+        if self.db.is_read_only() {
+            trace!("Retrieving from the remote cache skipped, the database is read-only");
+            return Ok(HashMap::new());
+        }
+
+        let local_hashes = self.get_local_hashes(&hashes)?;
+        let missing_hashes = hashes
+            .into_iter()
+            .filter(|hash| !local_hashes.contains(hash))
+            .collect::<Vec<_>>();
+        for hash in missing_hashes.iter() {
+            self.remove_stale_entry(hash, &self.cache_path.join(hash))?;
+        }
+
+        let cache_path = &self.cache_path;
+        let retrieved_hashes = missing_hashes
+            .par_iter()
+            .filter(|hash| {
+                let archive_path = get_archive_path(cache_path, hash);
+                remote_cache
+                    .retrieve(hash, &archive_path)
+                    .unwrap_or_else(|e| {
+                        warn!("Unable to retrieve {} from the remote cache: {:?}", hash, e);
+                        false
+                    })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut records = Vec::with_capacity(retrieved_hashes.len());
+        for hash in retrieved_hashes {
+            let archive_path = get_archive_path(&self.cache_path, &hash);
+            if self.verify_integrity && !self.verify_archive(&archive_path) {
+                warn!(
+                    "{} from the remote cache is corrupted and will be ignored",
+                    &hash
+                );
+                remove_items(&[&archive_path])?;
+                continue;
+            }
+
+            let code = match read_archive_result(&archive_path, self.cipher.as_ref()) {
+                Ok((code, _)) => code,
+                Err(e) => {
+                    warn!(
+                        "{} from the remote cache could not be read and will be ignored: {:?}",
+                        &hash, e
+                    );
+                    remove_items(&[&archive_path])?;
+                    continue;
+                }
+            };
+            let size = std::fs::metadata(&archive_path)?.len();
+            records.push((hash, code, size));
+        }
+        self.record_to_cache(&records)?;
+
+        let retrieved_hashes = records.into_iter().map(|(hash, _, _)| hash).collect();
+        self.get_many(retrieved_hashes, Some(true))
+    }
+
+    /// Uploads the given entries to the remote cache, skipping the ones it already contains.
+    /// Returns the hashes which were uploaded.
+    #[napi]
+    pub fn store_to_remote(&self, hashes: Vec<String>) -> anyhow::Result<Vec<String>> {
+        let Some(remote_cache) = &self.remote_cache else {
+            return Ok(vec![]);
+        };
+        if self.access_mode == CacheAccessMode::ReadOnly {
+            trace!("Storing to the remote cache skipped, the cache is read-only");
+            return Ok(vec![]);
+        }
+
+        let mut archives = vec![];
+        let mut temporary_archives = vec![];
+        for (hash, code) in self.get_local_codes(&hashes)? {
+            if let Some(archive_path) = self.archive_entry(&hash, code, &mut temporary_archives)? {
+                archives.push((hash, archive_path));
+            }
+        }
+
+        let stored_hashes = archives
+            .par_iter()
+            .filter(|(hash, archive_path)| {
+                let stored = remote_cache.exists(hash).and_then(|exists| {
+                    if exists {
+                        return Ok(false);
+                    }
+                    remote_cache.store(hash, archive_path)?;
+                    Ok(true)
+                });
+                stored.unwrap_or_else(|e| {
+                    warn!("Unable to store {} in the remote cache: {:?}", hash, e);
+                    false
+                })
+            })
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<_>>();
+
+        remove_items(&temporary_archives)?;
+        Ok(stored_hashes)
+    }
+
+    /// Returns the path of an archive holding the given entry.
+    /// Entries stored as directories are archived into a temporary file which is added to `temporary_archives`.
+    fn archive_entry(
+        &self,
+        hash: &str,
+        code: i16,
+        temporary_archives: &mut Vec<PathBuf>,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let archive_path = get_archive_path(&self.cache_path, hash);
+        if archive_path.is_file() {
+            return Ok(Some(archive_path));
+        }
+
+        let task_dir = self.cache_path.join(hash);
+        let Some(manifest) = read_manifest(&task_dir) else {
+            trace!("{} has no manifest and cannot be archived", hash);
+            return Ok(None);
+        };
+        let terminal_output = match self.read_terminal_output(hash) {
+            Ok(terminal_output) => terminal_output,
+            Err(e) => {
+                warn!("Unable to read the terminal output of {}: {:?}", hash, e);
+                return Ok(None);
+            }
+        };
+        // Every caller packs into its own path, so concurrent exports of an entry never share a file
+        let packed_path = staging_path(&self.cache_path, hash)?.with_extension("tar.zst");
+        write_archive(
+            &packed_path,
+            &task_dir,
+            &manifest,
+            code,
+            &terminal_output,
+            self.cipher.as_ref(),
+        )?;
+        temporary_archives.push(packed_path.clone());
+        Ok(Some(packed_path))
+    }
+
+    /// Writes the entries matching the filter into a single bundle file which can be
+    /// imported into the cache of another machine. Returns the hashes which were exported.
+    #[napi]
+    pub fn export_bundle(
+        &self,
+        bundle_path: String,
+        filter: Option<CacheExportFilter>,
+    ) -> anyhow::Result<Vec<String>> {
+        let filter = filter.unwrap_or_default();
+        trace!("Exporting cache entries matching {:?}", &filter);
+
+        let has_task_details = self.db.table_exists("task_details")?;
+        if !has_task_details && (filter.projects.is_some() || filter.targets.is_some()) {
+            // Without task details, no entry can match a project or target
+            return Ok(vec![]);
+        }
+
+        let mut conditions = vec![];
+        let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![];
+        let lists = [
+            ("cache_outputs.hash", &filter.hashes),
+            ("task_details.project", &filter.projects),
+            ("task_details.target", &filter.targets),
+        ];
+        for (column, list) in lists {
+            if let Some(list) = list {
+                values.push(Box::new(Rc::new(
+                    list.iter()
+                        .cloned()
+                        .map(Value::from)
+                        .collect::<Vec<Value>>(),
+                )));
+                conditions.push(format!("{} IN rarray(?{})", column, values.len()));
+            }
+        }
+        if let Some(max_age_days) = filter.max_age_days {
+            values.push(Box::new(format!("-{} days", max_age_days)));
+            conditions.push(format!(
+                "cache_outputs.created_at >= datetime('now', ?{})",
+                values.len()
+            ));
+        }
+
+        let details_columns = if has_task_details {
+            "task_details.project, task_details.target, task_details.configuration
+                FROM cache_outputs LEFT JOIN task_details ON task_details.hash = cache_outputs.hash"
+        } else {
+            "NULL AS project, NULL AS target, NULL AS configuration
+                FROM cache_outputs"
+        };
+        let query = format!(
+            "SELECT cache_outputs.hash, cache_outputs.code, {} {}",
+            details_columns,
+            if conditions.is_empty() {
+                String::new()
+            } else {
+                format!("WHERE {}", conditions.join(" AND "))
+            }
+        );
+
+        let entries = self
+            .db
+            .prepare(&query)?
+            .query_map(rusqlite::params_from_iter(values.iter()), |row| {
+                let project: Option<String> = row.get(2)?;
+                let target: Option<String> = row.get(3)?;
+                let configuration: Option<String> = row.get(4)?;
+                let task_details = project
+                    .zip(target)
+                    .map(|(project, target)| BundleTaskDetails {
+                        project,
+                        target,
+                        configuration,
+                    });
+                Ok(BundleEntry {
+                    hash: row.get(0)?,
+                    code: row.get(1)?,
+                    task_details,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut archives = vec![];
+        let mut temporary_archives = vec![];
+        let result = entries.into_iter().try_for_each(|entry| {
+            if let Some(archive_path) =
+                self.archive_entry(&entry.hash, entry.code, &mut temporary_archives)?
+            {
+                archives.push((entry, archive_path));
+            }
+            anyhow::Ok(())
+        });
+        let result = result.and_then(|_| write_bundle(Path::new(&bundle_path), &archives));
+        remove_items(&temporary_archives)?;
+        result?;
+
+        Ok(archives.into_iter().map(|(entry, _)| entry.hash).collect())
+    }
+
+    /// Imports the entries of a bundle written by `export_bundle`, along with their task details.
+    /// Entries which are already in the cache are skipped. Returns the hashes which were imported.
+    #[napi]
+    pub fn import_bundle(&self, bundle_path: String) -> anyhow::Result<Vec<String>> {
+        trace!("Importing cache bundle {}", &bundle_path);
+        let imported = read_bundle(Path::new(&bundle_path), &self.cache_path, |entry| {
+            if !self
+                .get_local_codes(std::slice::from_ref(&entry.hash))?
+                .is_empty()
+            {
+                return Ok(false);
+            }
+            self.remove_stale_entry(&entry.hash, &self.cache_path.join(&entry.hash))?;
+            Ok(true)
+        })?;
+
+        let mut records = Vec::with_capacity(imported.len());
+        let mut task_details = vec![];
+        for entry in imported {
+            let archive_path = get_archive_path(&self.cache_path, &entry.hash);
+            if self.verify_integrity && !self.verify_archive(&archive_path) {
+                warn!(
+                    "{} from the bundle is corrupted and will be ignored",
+                    &entry.hash
+                );
+                remove_items(&[&archive_path])?;
+                continue;
+            }
+
+            let size = std::fs::metadata(&archive_path)?.len();
+            records.push((entry.hash.clone(), entry.code, size));
+            if let Some(details) = entry.task_details {
+                task_details.push((entry.hash, details));
+            }
+        }
+
+        // The task details are recorded along with the entries, so that an entry is never recorded without them
+        let has_task_details = self.db.table_exists("task_details")?;
+        self.db.transaction(|tx| {
+            if has_task_details {
+                let mut statement = tx.prepare(
+                    "INSERT OR IGNORE INTO task_details (hash, project, target, configuration)
+                        VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for (hash, details) in task_details.iter() {
+                    statement.execute(params![
+                        hash,
+                        details.project,
+                        details.target,
+                        details.configuration
+                    ])?;
+                }
+            }
+            record_entries(tx, &records)
+        })?;
+
+        Ok(records.into_iter().map(|(hash, _, _)| hash).collect())
+    }
+
+    fn get_local_hashes(&self, hashes: &[String]) -> anyhow::Result<HashSet<String>> {
+        Ok(self
+            .get_local_codes(hashes)?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect())
+    }
+
+    fn get_local_codes(&self, hashes: &[String]) -> anyhow::Result<Vec<(String, i16)>> {
+        let values = Rc::new(
+            hashes
+                .iter()
+                .map(|hash| Value::from(hash.clone()))
+                .collect::<Vec<Value>>(),
+        );
+        let codes = self
+            .db
+            .prepare("SELECT hash, code FROM cache_outputs WHERE hash IN rarray(?1)")?
+            .query_map([values], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(codes)
+    }
+
     fn get_task_outputs_path_internal(&self, hash: &str) -> PathBuf {
         self.cache_path.join("terminalOutputs").join(hash)
     }
 
+    /// Reads the terminal output of an entry stored as a directory, decrypting it if the cache is encrypted.
+    /// A missing terminal output is read as empty.
+    fn read_terminal_output(&self, hash: &str) -> anyhow::Result<String> {
+        let path = self.get_task_outputs_path_internal(hash);
+        let Some(cipher) = &self.cipher else {
+            return Ok(read_to_string(path).unwrap_or_default());
+        };
+        let bytes = match read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(String::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(String::from_utf8(cipher.decrypt_bytes(&bytes)?)?)
+    }
+
+    fn write_terminal_output(&self, path: &Path, terminal_output: &str) -> anyhow::Result<()> {
+        match &self.cipher {
+            Some(cipher) => write(path, cipher.encrypt_bytes(terminal_output.as_bytes())?)?,
+            None => write(path, terminal_output)?,
+        }
+        Ok(())
+    }
+
     #[napi]
     pub fn get_task_outputs_path(&self, hash: String) -> String {
         self.get_task_outputs_path_internal(&hash)
             .to_normalized_string()
     }
 
-    fn record_to_cache(&self, hash: String, code: i16) -> anyhow::Result<()> {
-        trace!("Recording to cache: {}, {}", &hash, code);
-        self.db.execute(
-            "INSERT OR REPLACE INTO cache_outputs (hash, code) VALUES (?1, ?2)",
-            params![hash, code],
-        )?;
-        Ok(())
+    /// Records the hash, exit code and size of entries in a single transaction
+    fn record_to_cache(&self, entries: &[(String, i16, u64)]) -> anyhow::Result<()> {
+        self.db.transaction(|tx| record_entries(tx, entries))
     }
 
     #[napi]
@@ -201,52 +1308,499 @@ impl NxCache {
         &self,
         cached_result: CachedResult,
         outputs: Vec<String>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<CacheRestoreReport> {
         let outputs_path = Path::new(&cached_result.outputs_path);
+        let mut report = CacheRestoreReport::default();
 
-        let expanded_outputs = _expand_outputs(outputs_path, outputs)?;
+        if outputs_path.is_file() {
+            self.restore_from_archive(outputs_path, outputs, &mut report)?;
+            return Ok(report);
+        }
 
-        trace!("Removing expanded outputs: {:?}", &expanded_outputs);
-        remove_items(
-            expanded_outputs
-                .iter()
-                .map(|p| self.workspace_root.join(p))
-                .collect::<Vec<_>>()
-                .as_slice(),
-        )?;
+        let manifest = read_manifest(outputs_path);
+        let expanded_outputs = _expand_outputs(outputs_path, outputs)?;
+        match &manifest {
+            Some(manifest) if self.restore_mode == RestoreMode::Differential => {
+                self.remove_extraneous_outputs(&expanded_outputs, manifest, &mut report)?
+            }
+            _ => self.remove_outputs(&expanded_outputs, &mut report)?,
+        }
 
         trace!(
             "Copying Files from Cache {:?} -> {:?}",
             &outputs_path,
             &self.workspace_root
         );
-        _copy(outputs_path, &self.workspace_root)?;
+        match manifest {
+            Some(manifest) => self.restore_from_manifest(outputs_path, &manifest, &mut report)?,
+            // Entries written by older versions of Nx do not have a manifest
+            None => {
+                _copy(outputs_path, &self.workspace_root)?;
+                report.written = expanded_outputs;
+            }
+        }
+
+        trace!(
+            "Restored {} files, removed {}, left {} unchanged",
+            report.written.len(),
+            report.removed.len(),
+            report.unchanged
+        );
+        Ok(report)
+    }
+
+    fn restore_from_archive(
+        &self,
+        archive_path: &Path,
+        outputs: Vec<String>,
+        report: &mut CacheRestoreReport,
+    ) -> anyhow::Result<()> {
+        extract_archive(
+            archive_path,
+            &self.workspace_root,
+            self.cipher.as_ref(),
+            |manifest| {
+                let paths = manifest
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        let is_dir = matches!(entry.kind, ManifestEntryKind::Directory);
+                        (entry.path.clone(), is_dir)
+                    })
+                    .collect::<Vec<_>>();
+                let expanded_outputs = _expand_outputs_in_paths(&paths, outputs)?;
+
+                let differential = self.restore_mode == RestoreMode::Differential;
+                if differential {
+                    self.remove_extraneous_outputs(&expanded_outputs, manifest, report)?;
+                } else {
+                    self.remove_outputs(&expanded_outputs, report)?;
+                }
+
+                let mut unchanged_paths = HashSet::new();
+                for entry in manifest.entries.iter() {
+                    let is_dir = matches!(entry.kind, ManifestEntryKind::Directory);
+                    let dest = self.workspace_root.join(&entry.path);
+                    if differential && is_unchanged(entry, &dest) {
+                        if !is_dir {
+                            report.unchanged += 1;
+                        }
+                        unchanged_paths.insert(PathBuf::from(&entry.path));
+                        continue;
+                    }
+                    if differential && dest.symlink_metadata().is_ok() {
+                        remove_items(&[&dest])?;
+                    }
+                    if !is_dir {
+                        report.written.push(entry.path.clone());
+                    }
+                }
+                Ok(unchanged_paths)
+            },
+        )
+    }
+
+    fn restore_from_manifest(
+        &self,
+        outputs_path: &Path,
+        manifest: &CacheManifest,
+        report: &mut CacheRestoreReport,
+    ) -> anyhow::Result<()> {
+        let differential = self.restore_mode == RestoreMode::Differential;
+        let mut unchanged_paths = HashSet::new();
 
+        for entry in manifest.entries.iter() {
+            let dest = self.workspace_root.join(&entry.path);
+            if let Some(parent) = dest.parent() {
+                create_dir_all(parent)?;
+            }
+
+            if let ManifestEntryKind::Directory = entry.kind {
+                if !dest.is_dir() || dest.is_symlink() {
+                    if dest.symlink_metadata().is_ok() {
+                        remove_items(&[&dest])?;
+                    }
+                    create_dir_all(&dest)?;
+                }
+                continue;
+            }
+
+            if differential && is_unchanged(entry, &dest) {
+                report.unchanged += 1;
+                unchanged_paths.insert(entry.path.as_str());
+                continue;
+            }
+            if dest.symlink_metadata().is_ok() {
+                remove_items(&[&dest])?;
+            }
+
+            match &entry.kind {
+                ManifestEntryKind::File { blob, .. } => {
+                    let blob_path = self.blob_store.blob_path(blob);
+                    let src = if blob_path.exists() {
+                        blob_path
+                    } else {
+                        outputs_path.join(&entry.path)
+                    };
+                    copy_file(&src, &dest, self.copy_strategy)?;
+                }
+                ManifestEntryKind::Symlink { target } => symlink(target, &dest)?,
+                ManifestEntryKind::Directory => unreachable!(),
+            }
+            report.written.push(entry.path.clone());
+        }
+
+        // Blobs are shared between files, so their metadata is not reliable and is applied from the manifest.
+        // Children are handled before their directories, since writing them changes the directory.
+        // Files which already matched the cache are left untouched.
+        for entry in manifest.entries.iter().rev() {
+            if unchanged_paths.contains(entry.path.as_str()) {
+                continue;
+            }
+            let dest = self.workspace_root.join(&entry.path);
+            match entry.kind {
+                ManifestEntryKind::File { .. } if self.copy_strategy == CopyStrategy::Hardlink => {
+                    // The restored file is the blob itself, which is shared with other entries
+                    // and only ever loses its write permissions
+                    make_read_only(&dest)?;
+                }
+                _ => apply_metadata(&dest, entry)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_outputs(
+        &self,
+        expanded_outputs: &[String],
+        report: &mut CacheRestoreReport,
+    ) -> anyhow::Result<()> {
+        trace!("Removing expanded outputs: {:?}", expanded_outputs);
+        let paths = expanded_outputs
+            .iter()
+            .map(|p| self.workspace_root.join(p))
+            .collect::<Vec<_>>();
+        report.removed.extend(
+            expanded_outputs
+                .iter()
+                .zip(paths.iter())
+                .filter(|(_, path)| path.symlink_metadata().is_ok())
+                .map(|(output, _)| output.clone()),
+        );
+        remove_items(&paths)?;
+        Ok(())
+    }
+
+    /// Removes the files under the expanded outputs which are not part of the manifest
+    fn remove_extraneous_outputs(
+        &self,
+        expanded_outputs: &[String],
+        manifest: &CacheManifest,
+        report: &mut CacheRestoreReport,
+    ) -> anyhow::Result<()> {
+        let expected_paths = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<HashSet<_>>();
+
+        let mut extraneous_paths = vec![];
+        for expanded_output in expanded_outputs {
+            let mut walker = WalkDir::new(self.workspace_root.join(expanded_output)).into_iter();
+            while let Some(entry) = walker.next() {
+                let Ok(entry) = entry else {
+                    continue;
+                };
+                let path = entry
+                    .path()
+                    .strip_prefix(&self.workspace_root)?
+                    .to_normalized_string();
+                if expected_paths.contains(path.as_str()) {
+                    continue;
+                }
+
+                if entry.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+                extraneous_paths.push(entry.into_path());
+                report.removed.push(path);
+            }
+        }
+
+        trace!("Removing extraneous outputs: {:?}", &extraneous_paths);
+        remove_items(&extraneous_paths)?;
         Ok(())
     }
 
+    /// Removes the least recently accessed entries until the cache satisfies the given policy.
+    /// By default, entries which have not been accessed in the last 7 days are removed.
+    /// Pinned entries are never removed, but they count towards the size and number of entries.
     #[napi]
-    pub fn remove_old_cache_records(&self) -> anyhow::Result<()> {
-        let outdated_cache = self
-            .db
+    pub fn remove_old_cache_records(
+        &self,
+        policy: Option<CacheEvictionPolicy>,
+    ) -> anyhow::Result<CacheEvictionReport> {
+        let policy = policy.unwrap_or_default();
+        trace!("Evicting cache entries with {:?}", &policy);
+
+        let max_age = policy
+            .max_age_days
+            .map(|days| format!("-{} days", days))
+            .unwrap_or_else(|| String::from("-1000 years"));
+        let max_cache_size = policy.max_cache_size.unwrap_or(i64::MAX);
+        let max_entries = policy.max_entries.unwrap_or(u32::MAX);
+
+        // Walk the pinned entries first, then the entries from the most to the least
+        // recently accessed, and evict everything after the first one which does not fit in the budget
+        let mut outdated_hashes = vec![];
+        let mut kept_entries: u32 = 0;
+        let mut kept_size: i64 = 0;
+        let mut over_budget = false;
+        self.db
             .prepare(
-                "DELETE FROM cache_outputs WHERE accessed_at < datetime('now', '-7 days') RETURNING hash",
+                "SELECT hash, size, accessed_at < datetime('now', ?1) AS expired, pinned
+                    FROM cache_outputs
+                    ORDER BY pinned DESC, accessed_at DESC, rowid DESC",
             )?
-            .query_map(params![], |row| {
+            .query_map(params![max_age], |row| {
                 let hash: String = row.get(0)?;
-
-                Ok(vec![
-                    self.cache_path.join(&hash),
-                    self.get_task_outputs_path_internal(&hash),
-                ])
+                let size: i64 = row.get(1)?;
+                let expired: bool = row.get(2)?;
+                let pinned: bool = row.get(3)?;
+                Ok((hash, size, expired, pinned))
             })?
-            .filter_map(anyhow::Result::ok)
-            .flatten()
+            .filter_map(rusqlite::Result::ok)
+            .for_each(|(hash, size, expired, pinned)| {
+                if pinned {
+                    kept_entries += 1;
+                    kept_size = kept_size.saturating_add(size);
+                    return;
+                }
+                over_budget = over_budget
+                    || kept_entries >= max_entries
+                    || kept_size.saturating_add(size) > max_cache_size;
+                if expired || over_budget {
+                    outdated_hashes.push((hash, size));
+                } else {
+                    kept_entries += 1;
+                    kept_size += size;
+                }
+            });
+
+        let report = self.remove_entries(&outdated_hashes)?;
+
+        trace!("Evicted cache entries: {:?}", &report);
+        Ok(report)
+    }
+
+    /// Removes the records and files of the given entries, releasing their blobs
+    fn remove_entries(&self, entries: &[(String, i64)]) -> anyhow::Result<CacheEvictionReport> {
+        let mut report = CacheEvictionReport::default();
+        if entries.is_empty() {
+            return Ok(report);
+        }
+
+        // Only recorded entries hold blob references, and an archive takes precedence over a directory
+        let manifests = entries
+            .iter()
+            .map(|(hash, _)| self.entry_manifest(hash))
             .collect::<Vec<_>>();
+        let unreferenced_blobs = self.db.transaction(|tx| {
+            let mut unreferenced_blobs = vec![];
+            for ((hash, _), manifest) in entries.iter().zip(manifests.iter()) {
+                let removed =
+                    tx.execute("DELETE FROM cache_outputs WHERE hash = ?1", params![hash])? > 0;
+                if let (true, Some(manifest)) = (removed, manifest) {
+                    unreferenced_blobs
+                        .extend(self.blob_store.release_references(tx, &manifest.blobs())?);
+                }
+            }
+            Ok(unreferenced_blobs)
+        })?;
+        report.bytes_freed += self.blob_store.remove_blobs(&unreferenced_blobs) as i64;
 
-        remove_items(&outdated_cache)?;
+        let mut removed_files = Vec::with_capacity(entries.len() * 3);
+        for ((hash, size), manifest) in entries.iter().zip(manifests) {
+            let task_dir = self.cache_path.join(hash);
+            let terminal_output_path = self.get_task_outputs_path_internal(hash);
 
-        Ok(())
+            report.bytes_freed += match manifest {
+                Some(_) => std::fs::metadata(&terminal_output_path)
+                    .map(|metadata| metadata.len() as i64)
+                    .unwrap_or(0),
+                // Without a manifest, none of the entry's files are shared with other entries
+                None => *size,
+            };
+            report.entries_removed += 1;
+
+            removed_files.push(task_dir);
+            removed_files.push(terminal_output_path);
+            removed_files.push(get_archive_path(&self.cache_path, hash));
+        }
+
+        remove_items(&removed_files)?;
+        Ok(report)
+    }
+
+    /// Pins an entry so that it is never evicted, and adds the given tags to it.
+    /// Returns false if the cache does not contain the entry.
+    #[napi]
+    pub fn pin(&self, hash: String, tags: Option<Vec<String>>) -> anyhow::Result<bool> {
+        pin_entry(&self.db, &hash, &tags.unwrap_or_default())
+    }
+
+    /// Unpins an entry, keeping its tags. Returns false if the entry was not pinned.
+    #[napi]
+    pub fn unpin(&self, hash: String) -> anyhow::Result<bool> {
+        unpin_entry(&self.db, &hash)
+    }
+
+    /// Unpins every entry with the given tag. Returns the hashes which were unpinned.
+    #[napi]
+    pub fn unpin_by_tag(&self, tag: String) -> anyhow::Result<Vec<String>> {
+        unpin_entries_by_tag(&self.db, &tag)
+    }
+
+    /// Returns every entry with the given tag, pinned or not
+    #[napi]
+    pub fn get_entries_by_tag(&self, tag: String) -> anyhow::Result<Vec<TaggedCacheEntry>> {
+        get_entries_by_tag(&self.db, &tag)
+    }
+
+    /// Returns the size and contents of the cache, along with its hit ratio
+    #[napi]
+    pub fn get_stats(&self, options: Option<CacheStatsOptions>) -> anyhow::Result<CacheStats> {
+        get_cache_stats(&self.db, &options.unwrap_or_default())
+    }
+
+    /// Reconciles the records of the cache with the cache directory and the blob store,
+    /// and reports every mismatch between them. With `repair`, records and files which
+    /// cannot be restored are removed and the references of blobs are recounted.
+    /// Other processes should not write to the cache while it is being checked.
+    #[napi]
+    pub fn fsck(&self, repair: Option<bool>) -> anyhow::Result<CacheFsckReport> {
+        let repair = repair.unwrap_or(false);
+        let mut report = CacheFsckReport {
+            repaired: repair,
+            ..Default::default()
+        };
+
+        let contents = read_cache_dir(&self.cache_path)?;
+        let records = self
+            .db
+            .prepare("SELECT hash FROM cache_outputs")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<String>>>()?;
+
+        report.missing_entries = records
+            .iter()
+            .filter(|hash| !contents.contains(hash))
+            .cloned()
+            .collect();
+        report.untracked_entries = contents
+            .directories
+            .union(&contents.archives)
+            .filter(|hash| !records.contains(*hash))
+            .cloned()
+            .collect();
+        report.orphaned_terminal_outputs = contents
+            .terminal_outputs
+            .iter()
+            .filter(|hash| !records.contains(*hash))
+            .cloned()
+            .collect();
+        if self.link_task_details && self.db.table_exists("task_details")? {
+            report.missing_task_details = self
+                .db
+                .prepare(
+                    "SELECT hash FROM cache_outputs
+                        WHERE hash NOT IN (SELECT hash FROM task_details)",
+                )?
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+        }
+
+        let invalid_entries = report
+            .missing_entries
+            .iter()
+            .chain(report.missing_task_details.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        // Count the references of the entries which are kept
+        let mut blob_references: HashMap<String, (u64, u32)> = HashMap::new();
+        for hash in records.iter() {
+            if invalid_entries.contains(hash) {
+                continue;
+            }
+            if let Some(manifest) = self.entry_manifest(hash) {
+                for (blob, size) in manifest.blobs() {
+                    blob_references
+                        .entry(blob.to_string())
+                        .or_insert((size, 0))
+                        .1 += 1;
+                }
+            }
+        }
+        let recorded_references = self.blob_store.references(&self.db)?;
+        report.mismatched_blob_references = recorded_references
+            .keys()
+            .chain(blob_references.keys())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter(|blob| {
+                recorded_references.get(*blob).copied().unwrap_or(0)
+                    != blob_references.get(*blob).map_or(0, |(_, refs)| *refs)
+            })
+            .cloned()
+            .collect();
+        report.orphaned_blobs = self
+            .blob_store
+            .list()?
+            .into_iter()
+            .filter(|blob| !blob_references.contains_key(blob))
+            .collect();
+
+        for list in [
+            &mut report.missing_entries,
+            &mut report.untracked_entries,
+            &mut report.orphaned_terminal_outputs,
+            &mut report.missing_task_details,
+            &mut report.mismatched_blob_references,
+            &mut report.orphaned_blobs,
+        ] {
+            list.sort_unstable();
+        }
+
+        if repair && !report.is_consistent() {
+            let invalid_entries = invalid_entries
+                .into_iter()
+                .map(|hash| (hash, 0))
+                .collect::<Vec<_>>();
+            self.remove_entries(&invalid_entries)?;
+
+            let mut untracked_paths = vec![];
+            for hash in report.untracked_entries.iter() {
+                untracked_paths.push(self.cache_path.join(hash));
+                untracked_paths.push(get_archive_path(&self.cache_path, hash));
+            }
+            for hash in report.orphaned_terminal_outputs.iter() {
+                untracked_paths.push(self.get_task_outputs_path_internal(hash));
+            }
+            remove_items(&untracked_paths)?;
+
+            for blob in report.mismatched_blob_references.iter() {
+                let (size, refs) = blob_references.get(blob).copied().unwrap_or_default();
+                self.blob_store.set_references(&self.db, blob, size, refs)?;
+            }
+            for blob in report.orphaned_blobs.iter() {
+                self.blob_store.remove(blob);
+            }
+        }
+
+        trace!("Checked the cache: {:?}", &report);
+        Ok(report)
     }
 
     #[napi]
@@ -285,3 +1839,110 @@ impl NxCache {
         }
     }
 }
+
+/// Records entries in `cache_outputs`. Entries which are already recorded keep their pin and tags.
+fn record_entries(db: &Connection, entries: &[(String, i16, u64)]) -> anyhow::Result<()> {
+    let mut statement = db.prepare(
+        "INSERT INTO cache_outputs (hash, code, size) VALUES (?1, ?2, ?3)
+            ON CONFLICT (hash) DO UPDATE SET
+                code = excluded.code,
+                size = excluded.size,
+                created_at = CURRENT_TIMESTAMP,
+                accessed_at = CURRENT_TIMESTAMP",
+    )?;
+    for (hash, code, size) in entries {
+        trace!("Recording to cache: {}, {}, {} bytes", hash, code, size);
+        statement.execute(params![hash, code, size])?;
+    }
+    Ok(())
+}
+
+/// Checks whether a file or symlink in the workspace already matches an entry of a manifest
+fn is_unchanged(entry: &ManifestEntry, dest: &Path) -> bool {
+    let Ok(metadata) = dest.symlink_metadata() else {
+        return false;
+    };
+    match &entry.kind {
+        ManifestEntryKind::File { blob, size } => {
+            metadata.is_file()
+                && metadata.len() == *size
+                && hash_file_path(dest).is_some_and(|hash| &hash == blob)
+        }
+        ManifestEntryKind::Symlink { target } => {
+            metadata.is_symlink()
+                && read_link(dest).is_ok_and(|link| link.to_normalized_string() == *target)
+        }
+        ManifestEntryKind::Directory => metadata.is_dir(),
+    }
+}
+
+fn access_mode_from_env() -> Option<CacheAccessMode> {
+    let value = std::env::var("NX_CACHE_ACCESS_MODE").ok()?;
+    let access_mode = parse_access_mode(&value);
+    if access_mode.is_none() {
+        warn!(
+            "Ignoring NX_CACHE_ACCESS_MODE={}, expected read-write, read-only or write-only",
+            value
+        );
+    }
+    access_mode
+}
+
+fn parse_access_mode(value: &str) -> Option<CacheAccessMode> {
+    match value.to_lowercase().replace(['-', '_'], "").as_str() {
+        "readwrite" => Some(CacheAccessMode::ReadWrite),
+        "readonly" => Some(CacheAccessMode::ReadOnly),
+        "writeonly" => Some(CacheAccessMode::WriteOnly),
+        _ => None,
+    }
+}
+
+fn create_cache_layer(options: CacheLayerOptions) -> anyhow::Result<CacheLayer> {
+    let read_only = options.read_only.unwrap_or(false);
+    let storage = if read_only {
+        LocalFsStorage::read_only(&options.path)
+    } else {
+        LocalFsStorage::new(&options.path)?
+    };
+    Ok(CacheLayer {
+        path: options.path,
+        read_only,
+        storage,
+    })
+}
+
+fn create_remote_cache(options: RemoteCacheOptions) -> anyhow::Result<Box<dyn CacheStorage>> {
+    if options.url.starts_with("http://") || options.url.starts_with("https://") {
+        Ok(Box::new(HttpCacheStorage::new(HttpCacheStorageOptions {
+            url: options.url,
+            token: options.token,
+            max_retries: options.max_retries.unwrap_or(3),
+            max_concurrency: options.max_concurrency.unwrap_or(8),
+            timeout: Duration::from_secs(options.timeout_secs.unwrap_or(30) as u64),
+        })))
+    } else {
+        Ok(Box::new(LocalFsStorage::new(&options.url)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_access_modes() {
+        assert_eq!(
+            parse_access_mode("read-only"),
+            Some(CacheAccessMode::ReadOnly)
+        );
+        assert_eq!(
+            parse_access_mode("WRITE_ONLY"),
+            Some(CacheAccessMode::WriteOnly)
+        );
+        assert_eq!(
+            parse_access_mode("ReadWrite"),
+            Some(CacheAccessMode::ReadWrite)
+        );
+        assert_eq!(parse_access_mode("none"), None);
+    }
+}
@@ -0,0 +1,250 @@
+use std::io::{self, Read, Write};
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Name of the environment variable holding the key which cache entries are encrypted with
+pub const ENCRYPTION_KEY_ENV: &str = "NX_CLOUD_ENCRYPTION_KEY";
+
+/// Written at the start of every encrypted file
+const MAGIC: &[u8; 8] = b"NXENC\x00\x00\x01";
+/// Encrypted files are split into chunks so that they can be streamed
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = NONCE_LEN - 4;
+
+/// Encrypts and decrypts cache files with AES-256-GCM.
+///
+/// Files are encrypted in chunks. Every chunk is authenticated along with whether it is
+/// the last chunk, so that files which were modified, reordered or truncated fail to decrypt.
+#[derive(Clone)]
+pub struct CacheCipher {
+    key: LessSafeKey,
+}
+
+impl CacheCipher {
+    /// Derives the key from an arbitrary secret
+    pub fn new(secret: &str) -> anyhow::Result<Self> {
+        let key = digest(&SHA256, secret.as_bytes());
+        let key = UnboundKey::new(&AES_256_GCM, key.as_ref())
+            .map_err(|_| anyhow::anyhow!("Unable to create the cache encryption key"))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let secret = std::env::var(ENCRYPTION_KEY_ENV).map_err(|_| {
+            anyhow::anyhow!("{} has to be set to encrypt the cache", ENCRYPTION_KEY_ENV)
+        })?;
+        Self::new(&secret)
+    }
+
+    pub fn encrypt<W: Write>(&self, mut inner: W) -> io::Result<EncryptingWriter<W>> {
+        let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce_prefix)
+            .map_err(|_| io::Error::other("Unable to generate a nonce"))?;
+        inner.write_all(MAGIC)?;
+        inner.write_all(&nonce_prefix)?;
+
+        Ok(EncryptingWriter {
+            inner,
+            key: self.key.clone(),
+            nonce_prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE + TAG_LEN),
+        })
+    }
+
+    pub fn decrypt<R: Read>(&self, mut inner: R) -> io::Result<DecryptingReader<R>> {
+        let mut magic = [0; MAGIC.len()];
+        inner
+            .read_exact(&mut magic)
+            .map_err(|_| invalid_data("The file is not encrypted"))?;
+        if &magic != MAGIC {
+            return Err(invalid_data("The file is not encrypted"));
+        }
+        let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+        inner.read_exact(&mut nonce_prefix)?;
+
+        Ok(DecryptingReader {
+            inner,
+            key: self.key.clone(),
+            nonce_prefix,
+            counter: 0,
+            chunk: vec![],
+            position: 0,
+            finished: false,
+        })
+    }
+
+    pub fn encrypt_bytes(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut writer = self.encrypt(vec![])?;
+        writer.write_all(bytes)?;
+        writer.finish()
+    }
+
+    pub fn decrypt_bytes(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decrypted = vec![];
+        self.decrypt(bytes)?.read_to_end(&mut decrypted)?;
+        Ok(decrypted)
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..].copy_from_slice(&counter.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    key: LessSafeKey,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("The file is too large to be encrypted"))?;
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::from([last as u8]), &mut self.buffer)
+            .map_err(|_| io::Error::other("Unable to encrypt"))?;
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Writes the last chunk, which has to be done for the file to be decrypted
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        // The last chunk is always shorter than the others, which tells readers where the file ends
+        if self.buffer.len() == CHUNK_SIZE {
+            self.seal_chunk(false)?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    key: LessSafeKey,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    chunk: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    fn open_chunk(&mut self) -> io::Result<()> {
+        self.chunk.resize(CHUNK_SIZE + TAG_LEN, 0);
+        let mut length = 0;
+        while length < self.chunk.len() {
+            match self.inner.read(&mut self.chunk[length..])? {
+                0 => break,
+                read => length += read,
+            }
+        }
+        self.chunk.truncate(length);
+
+        let last = length < CHUNK_SIZE + TAG_LEN;
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        let plaintext_length = self
+            .key
+            .open_in_place(nonce, Aad::from([last as u8]), &mut self.chunk)
+            .map_err(|_| {
+                invalid_data("Unable to decrypt, the key is wrong or the file is corrupted")
+            })?
+            .len();
+        self.chunk.truncate(plaintext_length);
+        self.position = 0;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.open_chunk()?;
+        }
+
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_round_trip_files() {
+        let cipher = CacheCipher::new("secret").unwrap();
+        for size in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 7] {
+            let contents = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+            let encrypted = cipher.encrypt_bytes(&contents).unwrap();
+            assert_ne!(encrypted[MAGIC.len() + NONCE_PREFIX_LEN..], contents[..]);
+            assert_eq!(cipher.decrypt_bytes(&encrypted).unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn should_fail_with_the_wrong_key() {
+        let encrypted = CacheCipher::new("secret")
+            .unwrap()
+            .encrypt_bytes(b"terminal output")
+            .unwrap();
+
+        let cipher = CacheCipher::new("another secret").unwrap();
+        assert!(cipher.decrypt_bytes(&encrypted).is_err());
+    }
+
+    #[test]
+    fn should_fail_when_modified_or_truncated() {
+        let cipher = CacheCipher::new("secret").unwrap();
+        let contents = vec![7; 2 * CHUNK_SIZE];
+        let encrypted = cipher.encrypt_bytes(&contents).unwrap();
+
+        let mut modified = encrypted.clone();
+        modified[100] ^= 1;
+        assert!(cipher.decrypt_bytes(&modified).is_err());
+
+        let truncated = &encrypted[..MAGIC.len() + NONCE_PREFIX_LEN + CHUNK_SIZE + TAG_LEN];
+        assert!(cipher.decrypt_bytes(truncated).is_err());
+
+        assert!(cipher.decrypt_bytes(b"not encrypted").is_err());
+    }
+}
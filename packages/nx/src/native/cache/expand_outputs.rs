@@ -72,6 +72,73 @@ where
     Ok(found_paths)
 }
 
+/// Expands the given entries against a list of known paths instead of the file system.
+/// Each path is paired with whether it is a directory.
+/// This is used for restoring outputs from cache archives
+pub fn _expand_outputs_in_paths(
+    paths: &[(String, bool)],
+    entries: Vec<String>,
+) -> anyhow::Result<Vec<String>> {
+    let has_glob_pattern = entries.iter().any(|entry| contains_glob_pattern(entry));
+
+    if !has_glob_pattern {
+        trace!("No glob patterns found, checking if entries are in the paths");
+        let existing_entries = entries
+            .into_iter()
+            .filter(|entry| {
+                paths
+                    .iter()
+                    .any(|(path, _)| Path::new(path).starts_with(entry))
+            })
+            .collect();
+        return Ok(existing_entries);
+    }
+
+    let (regular_globs, negated_globs): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|entry| !entry.starts_with('!'));
+
+    let negated_globs = negated_globs
+        .into_iter()
+        .map(|s| format!("**/{}", &s[1..]))
+        .collect::<Vec<_>>();
+
+    let regular_globs = regular_globs
+        .into_iter()
+        .map(|s| {
+            if !s.ends_with('/') && paths.iter().any(|(path, is_dir)| *is_dir && path == &s) {
+                return format!("{}/", s);
+            }
+            s
+        })
+        .collect::<Vec<_>>();
+
+    trace!(?negated_globs, ?regular_globs, "Expanding globs in paths");
+
+    let glob_set = build_glob_set(&regular_globs)?;
+    let negated_glob_set = if negated_globs.is_empty() {
+        None
+    } else {
+        Some(build_glob_set(&negated_globs)?)
+    };
+
+    let found_paths = paths
+        .iter()
+        .filter(|(path, _)| {
+            // Negated globs exclude everything inside of the matched directories, like the walker does
+            let is_negated = negated_glob_set.as_ref().is_some_and(|negated_glob_set| {
+                Path::new(path)
+                    .ancestors()
+                    .any(|ancestor| negated_glob_set.is_match(ancestor))
+            });
+            !is_negated && glob_set.is_match(path)
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    Ok(found_paths)
+}
+
 fn partition_globs_into_map(globs: Vec<String>) -> anyhow::Result<HashMap<String, Vec<String>>> {
     globs
         .iter()
@@ -259,6 +326,44 @@ mod test {
         );
     }
 
+    #[test]
+    fn should_expand_outputs_in_paths() {
+        let paths = [
+            ("apps/web/.next", true),
+            ("apps/web/.next/cache", true),
+            ("apps/web/.next/cache/contents", false),
+            ("apps/web/.next/static", true),
+            ("apps/web/.next/static/contents", false),
+            ("apps/web/.next/content-file", false),
+            ("dist", true),
+            ("dist/main.js", false),
+        ]
+        .map(|(path, is_dir)| (path.to_string(), is_dir));
+
+        let result =
+            _expand_outputs_in_paths(&paths, vec!["dist".to_string(), "missing".to_string()])
+                .unwrap();
+        assert_eq!(result, vec!["dist"]);
+
+        let mut result = _expand_outputs_in_paths(
+            &paths,
+            vec![
+                "apps/web/.next".to_string(),
+                "!apps/web/.next/cache".to_string(),
+            ],
+        )
+        .unwrap();
+        result.sort();
+        assert_eq!(
+            result,
+            vec![
+                "apps/web/.next/content-file",
+                "apps/web/.next/static",
+                "apps/web/.next/static/contents"
+            ]
+        );
+    }
+
     #[test]
     fn should_get_files_for_outputs_with_glob() {
         let temp = setup_fs();
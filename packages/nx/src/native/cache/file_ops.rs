@@ -52,17 +52,17 @@ fn remove_trailing_single_dot(path: impl AsRef<Path>) -> PathBuf {
 }
 
 #[cfg(windows)]
-fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
     std::os::windows::fs::symlink_file(original, link)
 }
 
 #[cfg(unix)]
-fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
     std::os::unix::fs::symlink(original, link)
 }
 
 #[cfg(target_os = "wasi")]
-fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
     std::os::wasi::fs::symlink_path(original, link)
 }
 
@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::fs::read_dir;
+use std::path::Path;
+
+use regex::Regex;
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct CacheFsckReport {
+    /// Records whose directory or archive is missing from the cache directory
+    pub missing_entries: Vec<String>,
+    /// Directories and archives in the cache directory which have no record
+    pub untracked_entries: Vec<String>,
+    /// Terminal outputs which have no record
+    pub orphaned_terminal_outputs: Vec<String>,
+    /// Records whose task details are missing
+    pub missing_task_details: Vec<String>,
+    /// Blobs whose reference count does not match the entries which contain them
+    pub mismatched_blob_references: Vec<String>,
+    /// Files in the blob store which are not contained in any entry
+    pub orphaned_blobs: Vec<String>,
+    /// Whether the mismatches were repaired
+    pub repaired: bool,
+}
+
+impl CacheFsckReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_entries.is_empty()
+            && self.untracked_entries.is_empty()
+            && self.orphaned_terminal_outputs.is_empty()
+            && self.missing_task_details.is_empty()
+            && self.mismatched_blob_references.is_empty()
+            && self.orphaned_blobs.is_empty()
+    }
+}
+
+/// The hashes of the entries and terminal outputs found in a cache directory
+#[derive(Default, Debug)]
+pub struct CacheDirContents {
+    pub directories: HashSet<String>,
+    pub archives: HashSet<String>,
+    pub terminal_outputs: HashSet<String>,
+}
+
+impl CacheDirContents {
+    pub fn contains(&self, hash: &str) -> bool {
+        self.directories.contains(hash) || self.archives.contains(hash)
+    }
+}
+
+pub fn read_cache_dir(cache_path: &Path) -> anyhow::Result<CacheDirContents> {
+    let directory_regex = Regex::new(r"^\d+$").expect("Hash regex is invalid");
+    let archive_regex = Regex::new(r"^(\d+)\.tar\.zst$").expect("Archive regex is invalid");
+    let mut contents = CacheDirContents::default();
+
+    for entry in read_dir(cache_path)? {
+        let entry = entry?;
+        let Some(file_name) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+
+        if entry.file_type()?.is_dir() {
+            if directory_regex.is_match(&file_name) {
+                contents.directories.insert(file_name);
+            }
+        } else if let Some(captures) = archive_regex.captures(&file_name) {
+            contents.archives.insert(captures[1].to_string());
+        }
+    }
+
+    if let Ok(terminal_outputs) = read_dir(cache_path.join("terminalOutputs")) {
+        for entry in terminal_outputs {
+            contents
+                .terminal_outputs
+                .insert(entry?.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn should_read_entries_of_the_cache_dir() {
+        let temp = TempDir::new().unwrap();
+        temp.child("123/dist/a.txt").write_str("a").unwrap();
+        temp.child("234.tar.zst").write_str("archive").unwrap();
+        temp.child("234.packed.tar.zst")
+            .write_str("archive")
+            .unwrap();
+        temp.child("terminalOutputs/345")
+            .write_str("output")
+            .unwrap();
+        temp.child("blobs/ab/abc").write_str("blob").unwrap();
+        temp.child("run.json").write_str("{}").unwrap();
+
+        let contents = read_cache_dir(temp.path()).unwrap();
+
+        assert_eq!(contents.directories, HashSet::from(["123".to_string()]));
+        assert_eq!(contents.archives, HashSet::from(["234".to_string()]));
+        assert_eq!(
+            contents.terminal_outputs,
+            HashSet::from(["345".to_string()])
+        );
+        assert!(contents.contains("234"));
+        assert!(!contents.contains("345"));
+    }
+}
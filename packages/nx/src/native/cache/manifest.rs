@@ -0,0 +1,303 @@
+use std::fs::Metadata;
+use std::path::{Component, Path};
+
+use anyhow::anyhow;
+use filetime::FileTime;
+use rkyv::{Archive, Deserialize, Infallible, Serialize};
+use tracing::trace;
+
+/// Name of the manifest file stored at the root of every cache entry
+pub const MANIFEST_FILE: &str = ".nx-manifest";
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[archive(check_bytes)]
+pub enum ManifestEntryKind {
+    File { blob: String, size: u64 },
+    Directory,
+    Symlink { target: String },
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[archive(check_bytes)]
+pub struct ManifestEntry {
+    /// Path relative to the workspace root, always using forward slashes
+    pub path: String,
+    pub mode: u32,
+    /// Modification time in nanoseconds since the epoch
+    pub mtime: i64,
+    pub kind: ManifestEntryKind,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[archive(check_bytes)]
+pub struct CacheManifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl CacheManifest {
+    /// Returns the ids of the blobs referenced by this manifest, without duplicates
+    pub fn blobs(&self) -> Vec<(&str, u64)> {
+        let mut blobs = self
+            .entries
+            .iter()
+            .filter_map(|entry| match &entry.kind {
+                ManifestEntryKind::File { blob, size } => Some((blob.as_str(), *size)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        blobs.sort_unstable();
+        blobs.dedup();
+        blobs
+    }
+
+    /// Returns the total size of the files in this manifest
+    pub fn size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| match &entry.kind {
+                ManifestEntryKind::File { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+pub fn read_manifest<P: AsRef<Path>>(entry_dir: P) -> Option<CacheManifest> {
+    let manifest_path = entry_dir.as_ref().join(MANIFEST_FILE);
+    if !manifest_path.exists() {
+        return None;
+    }
+
+    let manifest = std::fs::read(&manifest_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| manifest_from_bytes(&bytes));
+
+    match manifest {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            trace!("could not read manifest {:?}: {:?}", manifest_path, e);
+            None
+        }
+    }
+}
+
+pub fn write_manifest<P: AsRef<Path>>(
+    entry_dir: P,
+    manifest: &CacheManifest,
+) -> anyhow::Result<()> {
+    let manifest_path = entry_dir.as_ref().join(MANIFEST_FILE);
+    std::fs::write(manifest_path, manifest_to_bytes(manifest)?)?;
+    Ok(())
+}
+
+/// Reads a manifest, rejecting it if any of its entries could be written outside of the workspace
+/// or reference a blob outside of the blob store
+pub fn manifest_from_bytes(bytes: &[u8]) -> anyhow::Result<CacheManifest> {
+    let archived = rkyv::check_archived_root::<CacheManifest>(bytes)
+        .map_err(|_| anyhow!("invalid manifest file"))?;
+    let manifest = <ArchivedCacheManifest as Deserialize<CacheManifest, Infallible>>::deserialize(
+        archived,
+        &mut rkyv::Infallible,
+    )?;
+
+    for entry in manifest.entries.iter() {
+        if !is_relative_path(&entry.path) {
+            return Err(anyhow!("invalid path in manifest: {:?}", entry.path));
+        }
+        if let ManifestEntryKind::File { blob, .. } = &entry.kind {
+            if blob.is_empty() || !blob.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(anyhow!("invalid blob in manifest: {:?}", blob));
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+/// Checks that a path stays below the directory it is joined to
+fn is_relative_path(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+pub fn manifest_to_bytes(manifest: &CacheManifest) -> anyhow::Result<Vec<u8>> {
+    Ok(rkyv::to_bytes::<_, 2048>(manifest)?.into_vec())
+}
+
+#[cfg(unix)]
+pub fn file_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+#[cfg(unix)]
+pub fn apply_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn apply_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    std::fs::set_permissions(path, permissions)
+}
+
+pub fn file_mtime(metadata: &Metadata) -> i64 {
+    let mtime = FileTime::from_last_modification_time(metadata);
+    mtime.unix_seconds() * NANOS_PER_SECOND + mtime.nanoseconds() as i64
+}
+
+/// Applies the modification time and mode of a manifest entry to `path`.
+/// The mode of symlinks is not applied since it cannot be changed on every platform.
+pub fn apply_metadata(path: &Path, entry: &ManifestEntry) -> std::io::Result<()> {
+    let mtime = FileTime::from_unix_time(
+        entry.mtime.div_euclid(NANOS_PER_SECOND),
+        entry.mtime.rem_euclid(NANOS_PER_SECOND) as u32,
+    );
+    if let ManifestEntryKind::Symlink { .. } = entry.kind {
+        return filetime::set_symlink_file_times(path, mtime, mtime);
+    }
+    // The mode is applied last because it can make the file read-only
+    filetime::set_file_times(path, mtime, mtime)?;
+    apply_mode(path, entry.mode)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn should_round_trip_manifests() {
+        let temp = TempDir::new().unwrap();
+        let manifest = CacheManifest {
+            entries: vec![
+                ManifestEntry {
+                    path: "dist".into(),
+                    mode: 0o755,
+                    mtime: 1_700_000_000_000_000_000,
+                    kind: ManifestEntryKind::Directory,
+                },
+                ManifestEntry {
+                    path: "dist/main.js".into(),
+                    mode: 0o644,
+                    mtime: 1_700_000_000_123_456_789,
+                    kind: ManifestEntryKind::File {
+                        blob: "123".into(),
+                        size: 10,
+                    },
+                },
+                ManifestEntry {
+                    path: "dist/copy.js".into(),
+                    mode: 0o644,
+                    mtime: 0,
+                    kind: ManifestEntryKind::File {
+                        blob: "123".into(),
+                        size: 10,
+                    },
+                },
+                ManifestEntry {
+                    path: "dist/link.js".into(),
+                    mode: 0o777,
+                    mtime: -1_500_000_000,
+                    kind: ManifestEntryKind::Symlink {
+                        target: "main.js".into(),
+                    },
+                },
+            ],
+        };
+
+        write_manifest(temp.path(), &manifest).unwrap();
+
+        let read = read_manifest(temp.path()).unwrap();
+        assert_eq!(read, manifest);
+        assert_eq!(read.blobs(), vec![("123", 10)]);
+        assert_eq!(read.size(), 20);
+    }
+
+    #[test]
+    fn should_ignore_invalid_manifests() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(read_manifest(temp.path()), None);
+
+        std::fs::write(temp.path().join(MANIFEST_FILE), "not a manifest").unwrap();
+        assert_eq!(read_manifest(temp.path()), None);
+    }
+
+    #[test]
+    fn should_reject_paths_outside_of_the_workspace() {
+        let mut paths = vec!["../outside", "dist/../../outside", ""];
+        if cfg!(windows) {
+            paths.extend(["C:\\outside", "\\outside"]);
+        } else {
+            paths.push("/outside");
+        }
+
+        for path in paths {
+            let manifest = CacheManifest {
+                entries: vec![ManifestEntry {
+                    path: path.into(),
+                    mode: 0o644,
+                    mtime: 0,
+                    kind: ManifestEntryKind::Directory,
+                }],
+            };
+            let bytes = manifest_to_bytes(&manifest).unwrap();
+            assert!(manifest_from_bytes(&bytes).is_err(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn should_reject_blobs_outside_of_the_blob_store() {
+        let manifest = CacheManifest {
+            entries: vec![ManifestEntry {
+                path: "dist/main.js".into(),
+                mode: 0o644,
+                mtime: 0,
+                kind: ManifestEntryKind::File {
+                    blob: "../../outside".into(),
+                    size: 10,
+                },
+            }],
+        };
+        let bytes = manifest_to_bytes(&manifest).unwrap();
+        assert!(manifest_from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn should_apply_metadata() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("script.sh");
+        std::fs::write(&path, "echo").unwrap();
+        let entry = ManifestEntry {
+            path: "script.sh".into(),
+            mode: 0o100755,
+            mtime: 1_600_000_000_123_456_789,
+            kind: ManifestEntryKind::File {
+                blob: "123".into(),
+                size: 4,
+            },
+        };
+
+        apply_metadata(&path, &entry).unwrap();
+
+        let metadata = path.metadata().unwrap();
+        assert_eq!(file_mtime(&metadata), entry.mtime);
+        #[cfg(unix)]
+        assert_eq!(file_mode(&metadata), entry.mode);
+    }
+}
@@ -3,4 +3,24 @@ pub mod file_ops;
 pub mod validate_outputs;
 
 #[cfg(not(target_arch = "wasm32"))]
-pub mod cache;
\ No newline at end of file
+pub mod archive;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod blob_store;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bundle;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cache;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod encryption;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fsck;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod manifest;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pins;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod staging;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod stats;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod storage;
@@ -0,0 +1,137 @@
+use rusqlite::params;
+
+use crate::native::db::connection::NxDbConnection;
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct TaggedCacheEntry {
+    pub hash: String,
+    /// Pinned entries are never removed by `remove_old_cache_records`
+    pub pinned: bool,
+    pub tags: Vec<String>,
+}
+
+/// Pins an entry and adds the given tags to it.
+/// Returns false if the cache does not contain the entry.
+pub fn pin_entry(db: &NxDbConnection, hash: &str, tags: &[String]) -> anyhow::Result<bool> {
+    for tag in tags {
+        validate_tag(tag)?;
+    }
+
+    let Some(existing_tags) = db.query_row(
+        "SELECT tags FROM cache_outputs WHERE hash = ?1",
+        params![hash],
+        |row| row.get::<_, Option<String>>(0),
+    )?
+    else {
+        return Ok(false);
+    };
+
+    let mut merged_tags = decode_tags(existing_tags.as_deref());
+    for tag in tags {
+        if !merged_tags.contains(tag) {
+            merged_tags.push(tag.clone());
+        }
+    }
+
+    db.execute(
+        "UPDATE cache_outputs SET pinned = 1, tags = ?2 WHERE hash = ?1",
+        params![hash, encode_tags(&merged_tags)],
+    )?;
+    Ok(true)
+}
+
+/// Unpins an entry, keeping its tags. Returns false if the entry was not pinned.
+pub fn unpin_entry(db: &NxDbConnection, hash: &str) -> anyhow::Result<bool> {
+    let updated = db.execute(
+        "UPDATE cache_outputs SET pinned = 0 WHERE hash = ?1 AND pinned",
+        params![hash],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Unpins every entry with the given tag. Returns the hashes which were unpinned.
+pub fn unpin_entries_by_tag(db: &NxDbConnection, tag: &str) -> anyhow::Result<Vec<String>> {
+    validate_tag(tag)?;
+    let hashes = db
+        .prepare(
+            "UPDATE cache_outputs SET pinned = 0
+                WHERE pinned AND instr(tags, ?1) > 0
+                RETURNING hash",
+        )?
+        .query_map(params![encode_tags(&[tag])], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(hashes)
+}
+
+/// Returns every entry with the given tag, pinned or not
+pub fn get_entries_by_tag(db: &NxDbConnection, tag: &str) -> anyhow::Result<Vec<TaggedCacheEntry>> {
+    validate_tag(tag)?;
+    let entries = db
+        .prepare(
+            "SELECT hash, pinned, tags FROM cache_outputs
+                WHERE instr(tags, ?1) > 0
+                ORDER BY created_at, rowid",
+        )?
+        .query_map(params![encode_tags(&[tag])], |row| {
+            let tags: Option<String> = row.get(2)?;
+            Ok(TaggedCacheEntry {
+                hash: row.get(0)?,
+                pinned: row.get(1)?,
+                tags: decode_tags(tags.as_deref()),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(entries)
+}
+
+fn validate_tag(tag: &str) -> anyhow::Result<()> {
+    if tag.is_empty() || tag.contains(',') {
+        return Err(anyhow::anyhow!(
+            "Invalid cache tag {:?}: tags cannot be empty or contain commas",
+            tag
+        ));
+    }
+    Ok(())
+}
+
+/// Tags are stored with a leading and trailing comma, so that a single tag
+/// can be matched by searching for it surrounded by commas
+fn encode_tags<T: AsRef<str>>(tags: &[T]) -> Option<String> {
+    if tags.is_empty() {
+        return None;
+    }
+    let tags = tags.iter().map(|tag| tag.as_ref()).collect::<Vec<_>>();
+    Some(format!(",{},", tags.join(",")))
+}
+
+fn decode_tags(tags: Option<&str>) -> Vec<String> {
+    tags.unwrap_or_default()
+        .split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_round_trip_tags() {
+        let tags = vec!["release".to_string(), "v1.2.3".to_string()];
+        let encoded = encode_tags(&tags);
+        assert_eq!(encoded.as_deref(), Some(",release,v1.2.3,"));
+        assert_eq!(decode_tags(encoded.as_deref()), tags);
+
+        assert_eq!(encode_tags::<String>(&[]), None);
+        assert!(decode_tags(None).is_empty());
+    }
+
+    #[test]
+    fn should_reject_invalid_tags() {
+        assert!(validate_tag("release").is_ok());
+        assert!(validate_tag("").is_err());
+        assert!(validate_tag("a,b").is_err());
+    }
+}
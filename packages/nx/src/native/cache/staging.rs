@@ -0,0 +1,93 @@
+use std::fs::{create_dir_all, read_dir, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use fs4::fs_std::FileExt;
+use fs_extra::remove_items;
+use tracing::trace;
+
+/// Directory where entries are written before they are moved into the cache
+const STAGING_DIR: &str = "staging";
+const LOCKS_DIR: &str = "locks";
+
+static STAGING_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// An exclusive lock held while an entry is written.
+///
+/// Hashes which share a prefix share a lock, so that the number of lock files stays bounded.
+pub struct EntryLock {
+    file: File,
+}
+
+impl EntryLock {
+    /// Waits until the lock of the given hash is available
+    pub fn acquire(cache_path: &Path, hash: &str) -> anyhow::Result<Self> {
+        let file = open_lock_file(cache_path, hash)?;
+        trace!("Getting lock for {}", hash);
+        file.lock_exclusive()
+            .map_err(|e| anyhow::anyhow!("Unable to lock {}: {:?}", hash, e))?;
+        Ok(Self { file })
+    }
+
+    /// Returns `None` if the lock of the given hash is held by someone else
+    pub fn try_acquire(cache_path: &Path, hash: &str) -> anyhow::Result<Option<Self>> {
+        let file = open_lock_file(cache_path, hash)?;
+        match file.try_lock_exclusive() {
+            Ok(_) => Ok(Some(Self { file })),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl Drop for EntryLock {
+    fn drop(&mut self) {
+        self.file.unlock().ok();
+    }
+}
+
+fn open_lock_file(cache_path: &Path, hash: &str) -> anyhow::Result<File> {
+    let locks_dir = cache_path.join(LOCKS_DIR);
+    create_dir_all(&locks_dir)?;
+    let prefix = hash.get(..2).unwrap_or(hash);
+    Ok(File::create(locks_dir.join(format!("{}.lock", prefix)))?)
+}
+
+/// Returns a new path in the staging directory to write an entry for the given hash to.
+/// The lock of the hash has to be held while the path is in use.
+pub fn staging_path(cache_path: &Path, hash: &str) -> anyhow::Result<PathBuf> {
+    let staging_dir = cache_path.join(STAGING_DIR);
+    create_dir_all(&staging_dir)?;
+    Ok(staging_dir.join(format!(
+        "{}-{}-{}",
+        hash,
+        process::id(),
+        STAGING_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )))
+}
+
+/// Removes the staging paths left behind by writers which were interrupted.
+/// Paths whose lock is currently held belong to a running writer and are kept.
+pub fn sweep_staging(cache_path: &Path) -> anyhow::Result<()> {
+    let Ok(entries) = read_dir(cache_path.join(STAGING_DIR)) else {
+        return Ok(());
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        let Some(hash) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split(['-', '.']).next())
+        else {
+            continue;
+        };
+
+        if let Some(_lock) = EntryLock::try_acquire(cache_path, hash)? {
+            trace!("Removing abandoned staging path {:?}", &path);
+            remove_items(&[&path])?;
+        }
+    }
+
+    Ok(())
+}
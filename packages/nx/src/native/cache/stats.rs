@@ -0,0 +1,168 @@
+use rusqlite::params;
+
+use crate::native::db::connection::NxDbConnection;
+
+/// Statuses of task runs which were replayed from the cache
+const LOCAL_HIT_STATUSES: &str = "'local-cache', 'local-cache-kept-existing'";
+const REMOTE_HIT_STATUSES: &str = "'remote-cache'";
+/// Statuses of task runs which were executed
+const MISS_STATUSES: &str = "'success', 'failure'";
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct CacheStatsOptions {
+    /// Only count the task runs of the last number of days in the hit ratio.
+    /// Defaults to every recorded task run.
+    pub window_days: Option<u32>,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct CacheTargetUsage {
+    pub project: String,
+    pub target: String,
+    pub entries: u32,
+    pub size: i64,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct CacheEntryInfo {
+    pub hash: String,
+    pub project: Option<String>,
+    pub target: Option<String>,
+    pub size: i64,
+    /// Milliseconds since the epoch
+    pub created_at: i64,
+    /// Milliseconds since the epoch
+    pub accessed_at: i64,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct CacheStats {
+    pub total_size: i64,
+    pub entry_count: u32,
+    /// Entries and bytes per project and target, largest first.
+    /// Entries without task details are not included.
+    pub usage: Vec<CacheTargetUsage>,
+    pub oldest_entry: Option<CacheEntryInfo>,
+    pub newest_entry: Option<CacheEntryInfo>,
+    pub local_hits: u32,
+    pub remote_hits: u32,
+    pub misses: u32,
+    /// The share of task runs which were replayed from the cache, between 0 and 1
+    pub hit_ratio: f64,
+}
+
+pub fn get_cache_stats(
+    db: &NxDbConnection,
+    options: &CacheStatsOptions,
+) -> anyhow::Result<CacheStats> {
+    let mut stats = CacheStats::default();
+    let has_task_details = db.table_exists("task_details")?;
+
+    (stats.entry_count, stats.total_size) = db
+        .query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM cache_outputs",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?
+        .unwrap_or_default();
+
+    if has_task_details {
+        stats.usage = db
+            .prepare(
+                "SELECT project, target, COUNT(*), SUM(size) AS total_size
+                    FROM cache_outputs
+                        JOIN task_details ON task_details.hash = cache_outputs.hash
+                    GROUP BY project, target
+                    ORDER BY total_size DESC, project, target",
+            )?
+            .query_map([], |row| {
+                Ok(CacheTargetUsage {
+                    project: row.get(0)?,
+                    target: row.get(1)?,
+                    entries: row.get(2)?,
+                    size: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+    }
+
+    stats.oldest_entry = get_entry(db, has_task_details, "ASC")?;
+    stats.newest_entry = get_entry(db, has_task_details, "DESC")?;
+
+    if db.table_exists("task_history")? {
+        let window_start = options
+            .window_days
+            .map(|days| {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_millis() as i64)
+                    .unwrap_or(0);
+                now - days as i64 * 24 * 60 * 60 * 1000
+            })
+            .unwrap_or(i64::MIN);
+
+        (stats.local_hits, stats.remote_hits, stats.misses) = db
+            .query_row(
+                &format!(
+                    "SELECT
+                        COUNT(*) FILTER (WHERE status IN ({})),
+                        COUNT(*) FILTER (WHERE status IN ({})),
+                        COUNT(*) FILTER (WHERE status IN ({}))
+                    FROM task_history
+                    WHERE start >= ?1",
+                    LOCAL_HIT_STATUSES, REMOTE_HIT_STATUSES, MISS_STATUSES
+                ),
+                params![window_start],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?
+            .unwrap_or_default();
+
+        let runs = stats.local_hits + stats.remote_hits + stats.misses;
+        if runs > 0 {
+            stats.hit_ratio = (stats.local_hits + stats.remote_hits) as f64 / runs as f64;
+        }
+    }
+
+    Ok(stats)
+}
+
+fn get_entry(
+    db: &NxDbConnection,
+    has_task_details: bool,
+    order: &str,
+) -> anyhow::Result<Option<CacheEntryInfo>> {
+    let (details_columns, details_join) = if has_task_details {
+        (
+            "task_details.project, task_details.target",
+            "LEFT JOIN task_details ON task_details.hash = cache_outputs.hash",
+        )
+    } else {
+        ("NULL, NULL", "")
+    };
+
+    db.query_row(
+        &format!(
+            "SELECT cache_outputs.hash, {}, size,
+                unixepoch(created_at) * 1000, unixepoch(accessed_at) * 1000
+            FROM cache_outputs {}
+            ORDER BY created_at {order}, cache_outputs.rowid {order}
+            LIMIT 1",
+            details_columns, details_join
+        ),
+        [],
+        |row| {
+            Ok(CacheEntryInfo {
+                hash: row.get(0)?,
+                project: row.get(1)?,
+                target: row.get(2)?,
+                size: row.get(3)?,
+                created_at: row.get(4)?,
+                accessed_at: row.get(5)?,
+            })
+        },
+    )
+}
@@ -0,0 +1,292 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+use tracing::trace;
+
+use crate::native::cache::storage::CacheStorage;
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct HttpCacheStorageOptions {
+    /// The base url of the cache server. Archives are stored at `<url>/<hash>`.
+    pub url: String,
+    /// Sent as a bearer token with every request
+    pub token: Option<String>,
+    /// How many times a request is retried after a network error or a server error
+    pub max_retries: u32,
+    /// How many requests can be in flight at the same time
+    pub max_concurrency: u32,
+    /// How long a request can wait to read from or write to the server before it fails
+    pub timeout: Duration,
+}
+
+/// Stores archives on a server which implements `GET`, `PUT` and `HEAD` for `/<hash>`
+pub struct HttpCacheStorage {
+    options: HttpCacheStorageOptions,
+    agent: ureq::Agent,
+    in_flight: Mutex<u32>,
+    request_finished: Condvar,
+}
+
+impl HttpCacheStorage {
+    pub fn new(options: HttpCacheStorageOptions) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(options.timeout)
+            .timeout_write(options.timeout)
+            .build();
+        Self {
+            options,
+            agent,
+            in_flight: Mutex::new(0),
+            request_finished: Condvar::new(),
+        }
+    }
+
+    fn url(&self, hash: &str) -> String {
+        format!("{}/{}", self.options.url.trim_end_matches('/'), hash)
+    }
+
+    /// Sends a request for the given hash, retrying it with an exponential backoff
+    /// when it fails because of the network or the server. Returns `None` for a 404.
+    /// The file at `body` is sent as the body of the request.
+    fn request(
+        &self,
+        method: &str,
+        hash: &str,
+        body: Option<&Path>,
+    ) -> anyhow::Result<Option<ureq::Response>> {
+        let url = self.url(hash);
+        let mut attempt = 0;
+        loop {
+            let mut request = self.agent.request(method, &url);
+            if let Some(token) = &self.options.token {
+                request = request.set("Authorization", &format!("Bearer {}", token));
+            }
+
+            trace!("{} {} (attempt {})", method, &url, attempt + 1);
+            let response = match body {
+                Some(body) => {
+                    let size = fs::metadata(body)?.len();
+                    request
+                        .set("Content-Type", "application/octet-stream")
+                        .set("Content-Length", &size.to_string())
+                        .send(File::open(body)?)
+                }
+                None => request.call(),
+            };
+            let error = match response {
+                Ok(response) => return Ok(Some(response)),
+                Err(ureq::Error::Status(404, _)) => return Ok(None),
+                Err(error) => error,
+            };
+
+            let retryable = match &error {
+                ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+                ureq::Error::Transport(_) => true,
+            };
+            if !retryable || attempt >= self.options.max_retries {
+                return Err(anyhow::anyhow!("{} {} failed: {}", method, &url, error));
+            }
+
+            trace!("{} {} failed, retrying: {}", method, &url, error);
+            thread::sleep(INITIAL_RETRY_DELAY * 2u32.pow(attempt));
+            attempt += 1;
+        }
+    }
+
+    /// Blocks until there are less than `max_concurrency` requests in flight
+    fn acquire(&self) -> RequestPermit<'_> {
+        let mut in_flight = self.in_flight.lock();
+        while *in_flight >= self.options.max_concurrency.max(1) {
+            self.request_finished.wait(&mut in_flight);
+        }
+        *in_flight += 1;
+        RequestPermit { storage: self }
+    }
+}
+
+struct RequestPermit<'a> {
+    storage: &'a HttpCacheStorage,
+}
+
+impl Drop for RequestPermit<'_> {
+    fn drop(&mut self) {
+        *self.storage.in_flight.lock() -= 1;
+        self.storage.request_finished.notify_one();
+    }
+}
+
+impl CacheStorage for HttpCacheStorage {
+    fn exists(&self, hash: &str) -> anyhow::Result<bool> {
+        let _permit = self.acquire();
+        Ok(self.request("HEAD", hash, None)?.is_some())
+    }
+
+    fn retrieve(&self, hash: &str, destination: &Path) -> anyhow::Result<bool> {
+        let _permit = self.acquire();
+        let Some(response) = self.request("GET", hash, None)? else {
+            return Ok(false);
+        };
+
+        // Download next to the destination so that a failed download never leaves a partial archive
+        let tmp_path = destination.with_extension(format!("{}.download", process::id()));
+        let download = File::create(&tmp_path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            io::copy(&mut response.into_reader(), &mut writer)?;
+            writer.flush()
+        });
+        if let Err(e) = download {
+            fs::remove_file(&tmp_path).ok();
+            return Err(anyhow::anyhow!(
+                "Unable to download {}: {}",
+                self.url(hash),
+                e
+            ));
+        }
+
+        fs::rename(&tmp_path, destination)?;
+        Ok(true)
+    }
+
+    fn store(&self, hash: &str, archive_path: &Path) -> anyhow::Result<()> {
+        let _permit = self.acquire();
+        self.request("PUT", hash, Some(archive_path))?
+            .ok_or_else(|| anyhow::anyhow!("PUT {} failed: 404", self.url(hash)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    struct TestServer {
+        url: String,
+        requests: Arc<AtomicU32>,
+    }
+
+    /// Serves archives from memory, failing the first `failures` requests with a 500
+    fn start_server(failures: u32) -> TestServer {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let requests = Arc::new(AtomicU32::new(0));
+
+        let counter = requests.clone();
+        thread::spawn(move || {
+            let mut archives: HashMap<String, Vec<u8>> = HashMap::new();
+            for mut request in server.incoming_requests() {
+                let attempt = counter.fetch_add(1, Ordering::SeqCst);
+                let authorized = request.headers().iter().any(|header| {
+                    header.field.equiv("Authorization") && header.value == "Bearer secret"
+                });
+
+                let response = if attempt < failures {
+                    tiny_http::Response::from_data(vec![]).with_status_code(500)
+                } else if !authorized {
+                    tiny_http::Response::from_data(vec![]).with_status_code(401)
+                } else {
+                    let hash = request.url().trim_start_matches('/').to_string();
+                    match request.method() {
+                        tiny_http::Method::Put => {
+                            let mut body = vec![];
+                            request.as_reader().read_to_end(&mut body).unwrap();
+                            archives.insert(hash, body);
+                            tiny_http::Response::from_data(vec![]).with_status_code(201)
+                        }
+                        _ => match archives.get(&hash) {
+                            Some(body) => tiny_http::Response::from_data(body.clone()),
+                            None => tiny_http::Response::from_data(vec![]).with_status_code(404),
+                        },
+                    }
+                };
+                request.respond(response).unwrap();
+            }
+        });
+
+        TestServer { url, requests }
+    }
+
+    fn storage(url: &str, token: Option<&str>) -> HttpCacheStorage {
+        HttpCacheStorage::new(HttpCacheStorageOptions {
+            url: url.to_string(),
+            token: token.map(String::from),
+            max_retries: 2,
+            max_concurrency: 2,
+            timeout: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn should_store_and_retrieve_archives() {
+        let temp = TempDir::new().unwrap();
+        temp.child("123.tar.zst")
+            .write_str("archive contents")
+            .unwrap();
+        let server = start_server(0);
+        let storage = storage(&server.url, Some("secret"));
+
+        assert!(!storage.exists("123").unwrap());
+        assert!(!storage
+            .retrieve("123", &temp.path().join("missing.tar.zst"))
+            .unwrap());
+
+        storage
+            .store("123", &temp.path().join("123.tar.zst"))
+            .unwrap();
+        assert!(storage.exists("123").unwrap());
+
+        let destination = temp.path().join("retrieved.tar.zst");
+        assert!(storage.retrieve("123", &destination).unwrap());
+        assert_eq!(fs::read_to_string(destination).unwrap(), "archive contents");
+    }
+
+    #[test]
+    fn should_retry_server_errors() {
+        let server = start_server(2);
+        let storage = storage(&server.url, Some("secret"));
+
+        assert!(!storage.exists("123").unwrap());
+        assert_eq!(server.requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn should_not_retry_unauthorized_requests() {
+        let server = start_server(0);
+        let storage = storage(&server.url, None);
+
+        assert!(storage.exists("123").is_err());
+        assert_eq!(server.requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn should_time_out_unresponsive_servers() {
+        // Accepts connections but never answers them
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let _connections = listener.incoming().collect::<Vec<_>>();
+        });
+        let storage = HttpCacheStorage::new(HttpCacheStorageOptions {
+            url,
+            token: None,
+            max_retries: 0,
+            max_concurrency: 1,
+            timeout: Duration::from_millis(200),
+        });
+
+        assert!(storage.exists("123").is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tracing::trace;
+
+use crate::native::cache::archive::get_archive_path;
+use crate::native::cache::storage::CacheStorage;
+
+/// Stores archives in a directory, such as a network drive shared between machines
+pub struct LocalFsStorage {
+    root: PathBuf,
+}
+
+impl LocalFsStorage {
+    pub fn new<P: AsRef<Path>>(root: P) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    /// Reads archives from a directory which may not be writable, such as one baked into a CI image
+    pub fn read_only<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl CacheStorage for LocalFsStorage {
+    fn exists(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(get_archive_path(&self.root, hash).is_file())
+    }
+
+    fn retrieve(&self, hash: &str, destination: &Path) -> anyhow::Result<bool> {
+        let archive_path = get_archive_path(&self.root, hash);
+        if !archive_path.is_file() {
+            return Ok(false);
+        }
+
+        trace!("Copying {:?} -> {:?}", &archive_path, destination);
+        let tmp_path = destination.with_extension(format!("{}.tmp", process::id()));
+        fs::copy(&archive_path, &tmp_path)?;
+        fs::rename(&tmp_path, destination)?;
+        Ok(true)
+    }
+
+    fn store(&self, hash: &str, archive_path: &Path) -> anyhow::Result<()> {
+        let destination = get_archive_path(&self.root, hash);
+        trace!("Copying {:?} -> {:?}", archive_path, &destination);
+
+        // Other machines may be reading from the same directory,
+        // so the archive is only moved into place once it is complete
+        let tmp_path = destination.with_extension(format!("{}.tmp", process::id()));
+        fs::copy(archive_path, &tmp_path)?;
+        fs::rename(&tmp_path, &destination)?;
+        Ok(())
+    }
+}
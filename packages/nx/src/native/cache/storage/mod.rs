@@ -0,0 +1,17 @@
+use std::path::Path;
+
+pub mod http;
+pub mod local;
+
+/// A place where cache entries can be shared, stored as single archives addressed by their hash
+pub trait CacheStorage: Send + Sync {
+    /// Returns whether an archive is stored for the given hash
+    fn exists(&self, hash: &str) -> anyhow::Result<bool>;
+
+    /// Writes the archive stored for the given hash to `destination`.
+    /// Returns false when there is no archive for the hash.
+    fn retrieve(&self, hash: &str, destination: &Path) -> anyhow::Result<bool>;
+
+    /// Stores the archive at `archive_path` for the given hash
+    fn store(&self, hash: &str, archive_path: &Path) -> anyhow::Result<()>;
+}
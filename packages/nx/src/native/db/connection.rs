@@ -1,16 +1,46 @@
 use anyhow::Result;
-use rusqlite::{Connection, Error, OptionalExtension, Params, Row, Statement};
+use rusqlite::{
+    Connection, Error, OptionalExtension, Params, Row, Statement, Transaction, TransactionBehavior,
+};
 use std::thread;
 use std::time::{Duration, Instant};
 use tracing::trace;
 
+#[napi(string_enum)]
+#[derive(Debug, Default, PartialEq)]
+pub enum DbOpenMode {
+    /// The database file is created if it does not exist, and migrated to the schema of this version of Nx
+    #[default]
+    ReadWrite,
+    /// The database file is only read. It is never created or migrated, and nothing is written to it.
+    ReadOnly,
+    /// A new database is created in memory, and is gone once the connection is closed
+    InMemory,
+}
+
 pub struct NxDbConnection {
     pub conn: Connection,
+    mode: DbOpenMode,
 }
 
 impl NxDbConnection {
     pub fn new(connection: Connection) -> Self {
-        Self { conn: connection }
+        Self::with_mode(connection, DbOpenMode::ReadWrite)
+    }
+
+    pub fn with_mode(connection: Connection, mode: DbOpenMode) -> Self {
+        Self {
+            conn: connection,
+            mode,
+        }
+    }
+
+    pub fn mode(&self) -> DbOpenMode {
+        self.mode
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.mode == DbOpenMode::ReadOnly
     }
 
     pub fn execute<P: Params + Clone>(&self, sql: &str, params: P) -> Result<usize> {
@@ -37,6 +67,47 @@ impl NxDbConnection {
             .map_err(|e| anyhow::anyhow!("DB query error: \"{}\", {:?}", sql, e))
     }
 
+    /// Runs `f` in a transaction which is committed when `f` succeeds and rolled back when it fails.
+    /// The transaction takes the write lock when it begins, so that its writes do not fail with `DatabaseBusy` halfway through.
+    pub fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Transaction) -> Result<T>,
+    {
+        let tx = self
+            .retry_on_busy(|conn| Transaction::new_unchecked(conn, TransactionBehavior::Immediate))
+            .map_err(|e| anyhow::anyhow!("DB transaction error: {:?}", e))?;
+        let result = f(&tx)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    pub fn table_exists(&self, table: &str) -> Result<bool> {
+        Ok(self
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+                [table],
+                |row| row.get(0),
+            )?
+            .unwrap_or(false))
+    }
+
+    /// Runs the `CREATE TABLE IF NOT EXISTS` statement of a table.
+    /// Read-only connections cannot change the database, so a table missing from it is
+    /// created as an empty temporary table which only exists for this connection.
+    pub fn create_table(&self, table: &str, sql: &str) -> Result<()> {
+        if !self.is_read_only() {
+            return self.execute_batch(sql);
+        }
+        if self.table_exists(table)? {
+            return Ok(());
+        }
+        trace!(
+            "Creating a temporary {} table for a read-only database",
+            table
+        );
+        self.execute_batch(&sql.replacen("CREATE TABLE", "CREATE TEMP TABLE", 1))
+    }
+
     pub fn close(self) -> rusqlite::Result<(), (Connection, Error)> {
         self.conn
             .close()
@@ -73,3 +144,36 @@ impl NxDbConnection {
         operation(&self.conn)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn count_rows(db: &NxDbConnection) -> i64 {
+        db.query_row("SELECT COUNT(*) FROM test", [], |row| row.get(0))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_commit_or_roll_back_transactions() {
+        let db = NxDbConnection::new(Connection::open_in_memory().unwrap());
+        db.execute_batch("CREATE TABLE test (id INTEGER NOT NULL)")
+            .unwrap();
+
+        db.transaction(|tx| {
+            tx.execute("INSERT INTO test (id) VALUES (1)", [])?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count_rows(&db), 1);
+
+        let result = db.transaction(|tx| {
+            tx.execute("INSERT INTO test (id) VALUES (2)", [])?;
+            tx.execute("INSERT INTO test (id) VALUES (NULL)", [])?;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(count_rows(&db), 1);
+    }
+}
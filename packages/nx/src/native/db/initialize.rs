@@ -1,9 +1,11 @@
 use std::fs::{remove_file, File};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
-use tracing::{debug, trace};
-use rusqlite::{Connection, OpenFlags};
+use tracing::{debug, trace, warn};
+use rusqlite::{Connection, ErrorCode, OpenFlags};
 use fs4::fs_std::FileExt;
-use crate::native::db::connection::NxDbConnection;
+use crate::native::db::connection::{DbOpenMode, NxDbConnection};
+use crate::native::db::migrations::{get_schema_version, migrate, SCHEMA_VERSION};
 
 pub(super) struct LockFile {
     file: File,
@@ -37,48 +39,131 @@ pub(super) fn create_lock_file(db_path: &Path) -> anyhow::Result<LockFile> {
 }
 
 pub(super) fn initialize_db(nx_version: String, db_path: &PathBuf) -> anyhow::Result<NxDbConnection> {
+    // This runs while holding the lock file and before this process has any connection to the database
+    if is_corrupted(db_path) {
+        warn!("The database {:?} is corrupted and will be recreated", db_path);
+        remove_db_files(db_path)?;
+    }
+
     let c = create_connection(db_path)?;
 
     trace!(
-        "Checking if current existing database is compatible with Nx {}",
+        "Migrating the existing database to schema version {} for Nx {}",
+        SCHEMA_VERSION,
         nx_version
     );
-    let db_version = c.query_row(
-        "SELECT value FROM metadata WHERE key='NX_VERSION'",
-        [],
-        |row| {
-            let r: String = row.get(0)?;
-            Ok(r)
-        },
-    );
-    let c = match db_version {
-        Ok(Some(version)) if version == nx_version => {
-            trace!("Database is compatible with Nx {}", nx_version);
-            c
-        }
-        // If there is no version, it means that this database is new
-        Ok(None) => {
-            trace!("Recording Nx Version: {}", nx_version);
-            c.execute(
-                "INSERT OR REPLACE INTO metadata (key, value) VALUES ('NX_VERSION', ?)",
-                [nx_version],
-            )?;
-            c
-        }
-        _ => {
+    let c = match migrate(&c) {
+        Ok(()) => c,
+        // Recreating the database loses its history, so it is only done when it cannot be migrated
+        Err(e) => {
+            warn!("Unable to migrate the existing database, it will be recreated: {:?}", e);
             trace!("Disconnecting from existing incompatible database");
             c.close().map_err(|(_, error)| anyhow::Error::from(error))?;
             trace!("Removing existing incompatible database");
-            remove_file(db_path)?;
+            remove_db_files(db_path)?;
 
             trace!("Creating a new connection to a new database");
-            create_connection(db_path)?
+            let c = create_connection(db_path)?;
+            migrate(&c)?;
+            c
         }
     };
 
+    record_nx_version(&c, nx_version)?;
+
     Ok(c)
 }
 
+pub(super) fn initialize_in_memory_db(nx_version: String) -> anyhow::Result<NxDbConnection> {
+    trace!("Creating an in-memory database");
+    let conn = Connection::open_in_memory()
+        .map_err(|e| anyhow::anyhow!("Error creating connection {:?}", e))?;
+    let c = NxDbConnection::with_mode(conn, DbOpenMode::InMemory);
+    create_metadata_table(&c)?;
+    migrate(&c)?;
+    record_nx_version(&c, nx_version)?;
+    Ok(c)
+}
+
+/// Opens an existing database without creating, migrating or locking it
+pub(super) fn open_read_only_db(db_path: &Path) -> anyhow::Result<NxDbConnection> {
+    if !db_path.exists() {
+        return Err(anyhow::anyhow!(
+            "The database {:?} does not exist and cannot be created in read-only mode",
+            db_path
+        ));
+    }
+
+    trace!("Opening a read-only connection to {:?}", db_path);
+    let conn = Connection::open_with_flags(
+        db_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_FULL_MUTEX,
+    )
+    .map_err(|e| anyhow::anyhow!("Error creating connection {:?}", e))?;
+    conn.busy_handler(Some(|tries| tries < 6))
+        .map_err(|e| anyhow::anyhow!("Unable to set busy handler: {:?}", e))?;
+
+    let schema_version = get_schema_version(&conn)?;
+    if schema_version < SCHEMA_VERSION {
+        return Err(anyhow::anyhow!(
+            "The database {:?} has schema version {}, but version {} is required and read-only databases cannot be migrated",
+            db_path,
+            schema_version,
+            SCHEMA_VERSION
+        ));
+    }
+
+    Ok(NxDbConnection::with_mode(conn, DbOpenMode::ReadOnly))
+}
+
+pub(super) fn db_files(db_path: &Path) -> [PathBuf; 3] {
+    [
+        db_path.to_path_buf(),
+        db_path.with_extension("db-wal"),
+        db_path.with_extension("db-shm"),
+    ]
+}
+
+pub(super) fn remove_db_files(db_path: &Path) -> anyhow::Result<()> {
+    for path in db_files(db_path) {
+        match remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether the database exists but SQLite cannot read it.
+/// Only the header and the schema are read, so that this stays cheap enough to run on every connection.
+fn is_corrupted(db_path: &Path) -> bool {
+    if !db_path.exists() {
+        return false;
+    }
+    let result = Connection::open_with_flags(
+        db_path,
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI,
+    )
+    .and_then(|c| c.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())));
+    match result {
+        Err(rusqlite::Error::SqliteFailure(e, _)) => {
+            matches!(e.code, ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+        }
+        _ => false,
+    }
+}
+
+fn record_nx_version(c: &NxDbConnection, nx_version: String) -> anyhow::Result<()> {
+    trace!("Recording Nx Version: {}", nx_version);
+    c.execute(
+        "INSERT OR REPLACE INTO metadata (key, value) VALUES ('NX_VERSION', ?)",
+        [nx_version],
+    )?;
+    Ok(())
+}
+
 fn create_connection(db_path: &PathBuf) -> anyhow::Result<NxDbConnection> {
     match open_database_connection(db_path) {
         Ok(connection) => {
@@ -0,0 +1,230 @@
+use std::fs::metadata;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, ErrorCode};
+use tracing::{debug, trace, warn};
+
+use crate::native::db::connection::NxDbConnection;
+use crate::native::db::initialize::{db_files, initialize_db, remove_db_files};
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct DbMaintenanceOptions {
+    /// Task runs which ended more than this number of days ago are removed. Defaults to 30.
+    pub max_task_run_age_days: Option<u32>,
+    /// The maximum number of task runs to keep. The most recent ones are kept.
+    pub max_task_runs: Option<u32>,
+    /// Rewrite the database to give the space of removed rows back to the filesystem. Defaults to true.
+    pub vacuum: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct DbMaintenanceReport {
+    pub task_runs_removed: u32,
+    /// Task details which were no longer referenced by task runs or cache entries
+    pub task_details_removed: u32,
+    /// The problems found by the integrity check. The database is recreated when there are any.
+    pub integrity_errors: Vec<String>,
+    /// Whether the database was recreated because it is corrupted
+    pub rebuilt: bool,
+    /// How much the files of the database shrank
+    pub bytes_freed: i64,
+}
+
+pub(super) fn maintain_db(
+    nx_version: String,
+    db_path: &PathBuf,
+    options: &DbMaintenanceOptions,
+) -> anyhow::Result<DbMaintenanceReport> {
+    let size_before = db_files_size(db_path);
+    let mut report = DbMaintenanceReport::default();
+
+    trace!("Checking the integrity of {:?}", db_path);
+    report.integrity_errors = match Connection::open(db_path).and_then(|c| check_integrity(&c)) {
+        Ok(errors) => errors,
+        Err(rusqlite::Error::SqliteFailure(e, message))
+            if matches!(e.code, ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) =>
+        {
+            vec![message.unwrap_or_else(|| e.to_string())]
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // This runs while holding the lock file, so no other process is connecting to the database
+    if !report.integrity_errors.is_empty() {
+        warn!(
+            "The database {:?} is corrupted and will be recreated: {:?}",
+            db_path, &report.integrity_errors
+        );
+        remove_db_files(db_path)?;
+        initialize_db(nx_version, db_path)?;
+        report.rebuilt = true;
+    } else {
+        let c = initialize_db(nx_version, db_path)?;
+        report.task_runs_removed = prune_task_history(&c, options)?;
+        report.task_details_removed = prune_task_details(&c)?;
+
+        debug!("Checkpointing the write-ahead log");
+        checkpoint(&c)?;
+        if options.vacuum.unwrap_or(true) {
+            debug!("Vacuuming the database");
+            c.execute_batch("VACUUM")?;
+            checkpoint(&c)?;
+        }
+    }
+
+    report.bytes_freed = size_before as i64 - db_files_size(db_path) as i64;
+    trace!("Database maintenance finished: {:?}", &report);
+    Ok(report)
+}
+
+/// Returns the problems found in the database, which is empty when it is healthy
+fn check_integrity(c: &Connection) -> rusqlite::Result<Vec<String>> {
+    let results = c
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(results
+        .into_iter()
+        .filter(|result| result != "ok")
+        .collect())
+}
+
+fn prune_task_history(c: &NxDbConnection, options: &DbMaintenanceOptions) -> anyhow::Result<u32> {
+    if !c.table_exists("task_history")? {
+        return Ok(0);
+    }
+
+    // Task runs record their start and end in milliseconds since the epoch
+    let mut removed = c.execute(
+        "DELETE FROM task_history
+            WHERE end < (CAST(strftime('%s', 'now') AS INTEGER) - ?1 * 86400) * 1000",
+        params![options.max_task_run_age_days.unwrap_or(30)],
+    )?;
+    if let Some(max_task_runs) = options.max_task_runs {
+        removed += c.execute(
+            "DELETE FROM task_history WHERE id NOT IN (
+                SELECT id FROM task_history ORDER BY end DESC, id DESC LIMIT ?1
+            )",
+            params![max_task_runs],
+        )?;
+    }
+    Ok(removed as u32)
+}
+
+fn prune_task_details(c: &NxDbConnection) -> anyhow::Result<u32> {
+    if !c.table_exists("task_details")? {
+        return Ok(0);
+    }
+
+    let mut query = String::from("DELETE FROM task_details WHERE 1");
+    for table in ["task_history", "cache_outputs"] {
+        if c.table_exists(table)? {
+            query.push_str(&format!(" AND hash NOT IN (SELECT hash FROM {})", table));
+        }
+    }
+    Ok(c.execute(&query, [])? as u32)
+}
+
+fn checkpoint(c: &NxDbConnection) -> anyhow::Result<()> {
+    c.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    Ok(())
+}
+
+fn db_files_size(db_path: &Path) -> u64 {
+    db_files(db_path)
+        .iter()
+        .filter_map(|path| metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    fn record_task_runs(db_path: &PathBuf, runs: &[(&str, i64)]) {
+        let c = initialize_db(String::from("0.0.0"), db_path).unwrap();
+        c.execute_batch(
+            "CREATE TABLE task_details (hash TEXT PRIMARY KEY NOT NULL, project TEXT NOT NULL);
+            CREATE TABLE task_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                hash TEXT NOT NULL,
+                end TIMESTAMP NOT NULL
+            );",
+        )
+        .unwrap();
+        for (hash, end) in runs {
+            c.execute(
+                "INSERT OR IGNORE INTO task_details (hash, project) VALUES (?1, 'proj')",
+                params![hash],
+            )
+            .unwrap();
+            c.execute(
+                "INSERT INTO task_history (hash, end) VALUES (?1, ?2)",
+                params![hash, end],
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn should_prune_old_task_runs() {
+        let temp = TempDir::new().unwrap();
+        let db_path = temp.child("test.db").to_path_buf();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let day = 24 * 60 * 60 * 1000;
+        record_task_runs(
+            &db_path,
+            &[("old", now - 40 * day), ("123", now - day), ("234", now)],
+        );
+
+        let report = maintain_db(
+            String::from("0.0.0"),
+            &db_path,
+            &DbMaintenanceOptions {
+                max_task_runs: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(report.task_runs_removed, 2);
+        assert_eq!(report.task_details_removed, 2);
+        assert!(report.integrity_errors.is_empty());
+        assert!(!report.rebuilt);
+
+        let c = initialize_db(String::from("0.0.0"), &db_path).unwrap();
+        let remaining: Option<String> = c
+            .query_row("SELECT group_concat(hash) FROM task_details", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(remaining.as_deref(), Some("234"));
+    }
+
+    #[test]
+    fn should_rebuild_corrupted_databases() {
+        let temp = TempDir::new().unwrap();
+        let db_path = temp.child("test.db");
+        db_path.write_binary(&[7; 4096]).unwrap();
+
+        let report = maintain_db(
+            String::from("0.0.0"),
+            &db_path.to_path_buf(),
+            &DbMaintenanceOptions::default(),
+        )
+        .unwrap();
+
+        assert!(report.rebuilt);
+        assert!(!report.integrity_errors.is_empty());
+        let c = initialize_db(String::from("0.0.0"), &db_path.to_path_buf()).unwrap();
+        assert!(c.table_exists("metadata").unwrap());
+    }
+}
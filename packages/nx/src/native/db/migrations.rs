@@ -0,0 +1,236 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::{debug, trace};
+
+use crate::native::db::connection::NxDbConnection;
+
+/// A forward change to the schema of the database.
+///
+/// Tables are created by the code which uses them, so migrations only have to bring
+/// tables written by older versions of Nx up to date, and must do nothing for tables which do not exist yet.
+pub(super) struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub up: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+/// Migrations in the order they are applied. Versions have to be consecutive, starting at 1.
+pub(super) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Record the size of cache entries",
+        up: |conn| add_column(conn, "cache_outputs", "size", "INTEGER NOT NULL DEFAULT 0"),
+    },
+    Migration {
+        version: 2,
+        description: "Pin and tag cache entries",
+        up: |conn| {
+            add_column(
+                conn,
+                "cache_outputs",
+                "pinned",
+                "BOOLEAN NOT NULL DEFAULT 0",
+            )?;
+            add_column(conn, "cache_outputs", "tags", "TEXT")
+        },
+    },
+];
+
+/// The schema version of databases written by this version of Nx
+pub(super) const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Applies the migrations which are newer than the schema version of the database in a single transaction.
+/// Nothing is changed if any of them fails.
+pub(super) fn migrate(c: &NxDbConnection) -> anyhow::Result<()> {
+    apply_migrations(&c.conn, MIGRATIONS)
+}
+
+/// Returns the schema version recorded in the database.
+/// Databases written before migrations were introduced have version 0.
+pub(super) fn get_schema_version(conn: &Connection) -> anyhow::Result<u32> {
+    let version = conn
+        .query_row(
+            "SELECT value FROM metadata WHERE key = 'SCHEMA_VERSION'",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|version| version.parse::<u32>())
+        .transpose()?
+        .unwrap_or(0);
+    Ok(version)
+}
+
+fn apply_migrations(conn: &Connection, migrations: &[Migration]) -> anyhow::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let current_version = get_schema_version(&tx)?;
+    let latest_version = migrations.last().map_or(0, |migration| migration.version);
+    if current_version > latest_version {
+        return Err(anyhow::anyhow!(
+            "The database has schema version {} which is newer than the supported version {}",
+            current_version,
+            latest_version
+        ));
+    }
+
+    for migration in migrations
+        .iter()
+        .filter(|migration| migration.version > current_version)
+    {
+        debug!(
+            "Applying database migration {}: {}",
+            migration.version, migration.description
+        );
+        (migration.up)(&tx).map_err(|e| {
+            anyhow::anyhow!(
+                "Database migration {} ({}) failed: {:?}",
+                migration.version,
+                migration.description,
+                e
+            )
+        })?;
+    }
+
+    tx.execute(
+        "INSERT OR REPLACE INTO metadata (key, value) VALUES ('SCHEMA_VERSION', ?1)",
+        params![latest_version.to_string()],
+    )?;
+    tx.commit()?;
+    trace!(
+        "Database schema migrated from version {} to {}",
+        current_version,
+        latest_version
+    );
+    Ok(())
+}
+
+/// Adds a column to a table, unless the table does not exist or already has the column
+fn add_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let table_exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [table],
+        |row| row.get(0),
+    )?;
+    let column_exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    if table_exists && !column_exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE metadata (
+                key TEXT NOT NULL PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn schema_version(conn: &Connection) -> String {
+        conn.query_row(
+            "SELECT value FROM metadata WHERE key = 'SCHEMA_VERSION'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn should_have_consecutive_versions() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn should_migrate_tables_of_older_versions() {
+        let conn = create_connection();
+        conn.execute_batch(
+            "CREATE TABLE cache_outputs (
+                hash TEXT PRIMARY KEY NOT NULL,
+                code INTEGER NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                accessed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            );
+            INSERT INTO cache_outputs (hash, code) VALUES ('123', 0);",
+        )
+        .unwrap();
+
+        apply_migrations(&conn, MIGRATIONS).unwrap();
+
+        let (size, pinned, tags): (i64, bool, Option<String>) = conn
+            .query_row(
+                "SELECT size, pinned, tags FROM cache_outputs WHERE hash = '123'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((size, pinned, tags), (0, false, None));
+        assert_eq!(schema_version(&conn), SCHEMA_VERSION.to_string());
+
+        // Applying them again does nothing
+        apply_migrations(&conn, MIGRATIONS).unwrap();
+    }
+
+    #[test]
+    fn should_skip_tables_which_do_not_exist() {
+        let conn = create_connection();
+        apply_migrations(&conn, MIGRATIONS).unwrap();
+        assert_eq!(schema_version(&conn), SCHEMA_VERSION.to_string());
+    }
+
+    #[test]
+    fn should_roll_back_failed_migrations() {
+        let conn = create_connection();
+        let migrations = [
+            Migration {
+                version: 1,
+                description: "Create a table",
+                up: |conn| conn.execute_batch("CREATE TABLE first (id INTEGER)"),
+            },
+            Migration {
+                version: 2,
+                description: "Fail",
+                up: |conn| conn.execute_batch("INSERT INTO missing VALUES (1)"),
+            },
+        ];
+
+        assert!(apply_migrations(&conn, &migrations).is_err());
+
+        let first_exists: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'first')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!first_exists);
+    }
+
+    #[test]
+    fn should_reject_newer_schema_versions() {
+        let conn = create_connection();
+        conn.execute_batch("INSERT INTO metadata (key, value) VALUES ('SCHEMA_VERSION', '1000')")
+            .unwrap();
+        assert!(apply_migrations(&conn, MIGRATIONS).is_err());
+    }
+}
@@ -1,23 +1,42 @@
 pub mod connection;
 mod initialize;
+pub mod maintenance;
+mod migrations;
 
-use crate::native::db::connection::NxDbConnection;
+use crate::native::db::connection::{DbOpenMode, NxDbConnection};
+use crate::native::db::maintenance::{DbMaintenanceOptions, DbMaintenanceReport};
 use crate::native::machine_id::get_machine_id;
 use napi::bindgen_prelude::External;
 use std::fs::create_dir_all;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
 use tracing::{trace, trace_span};
 
+/// Connects to the database of the workspace, or to a new in-memory database.
+/// Read-only connections never create the database or its lock file.
 #[napi]
 pub fn connect_to_nx_db(
     cache_dir: String,
     nx_version: String,
     db_name: Option<String>,
+    mode: Option<DbOpenMode>,
 ) -> anyhow::Result<External<NxDbConnection>> {
-    let cache_dir_buf = PathBuf::from(cache_dir);
-    let db_path = cache_dir_buf.join(format!("{}.db", db_name.unwrap_or_else(get_machine_id)));
-    create_dir_all(cache_dir_buf)?;
+    let cache_dir = PathBuf::from(cache_dir);
+    match mode.unwrap_or_default() {
+        DbOpenMode::ReadWrite => {}
+        DbOpenMode::ReadOnly => {
+            let db_path = get_db_path(&cache_dir, db_name);
+            return Ok(External::new(initialize::open_read_only_db(&db_path)?));
+        }
+        DbOpenMode::InMemory => {
+            return Ok(External::new(initialize::initialize_in_memory_db(
+                nx_version,
+            )?));
+        }
+    }
+
+    let db_path = get_db_path(&cache_dir, db_name);
+    create_dir_all(&cache_dir)?;
 
     let _ = trace_span!("process", id = process::id()).entered();
     trace!("Creating connection to {:?}", db_path);
@@ -30,3 +49,59 @@ pub fn connect_to_nx_db(
 
     Ok(External::new(c))
 }
+
+/// Removes old task runs, gives unused space back to the filesystem and checks the integrity of the database.
+/// A corrupted database is recreated, so this should run before any connection to the database is opened.
+/// Databases which cannot be read at all are also recreated by `connectToNxDb`.
+#[napi]
+pub fn maintain_nx_db(
+    cache_dir: String,
+    nx_version: String,
+    db_name: Option<String>,
+    options: Option<DbMaintenanceOptions>,
+) -> anyhow::Result<DbMaintenanceReport> {
+    let cache_dir = PathBuf::from(cache_dir);
+    let db_path = get_db_path(&cache_dir, db_name);
+    create_dir_all(&cache_dir)?;
+
+    let _ = trace_span!("process", id = process::id()).entered();
+    trace!("Maintaining {:?}", db_path);
+    let lock_file = initialize::create_lock_file(&db_path)?;
+
+    let report = maintenance::maintain_db(nx_version, &db_path, &options.unwrap_or_default());
+
+    initialize::unlock_file(&lock_file);
+    report
+}
+
+fn get_db_path(cache_dir: &Path, db_name: Option<String>) -> PathBuf {
+    cache_dir.join(format!("{}.db", db_name.unwrap_or_else(get_machine_id)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn should_recreate_corrupted_databases_when_connecting() {
+        let temp = TempDir::new().unwrap();
+        temp.child("test.db").write_binary(&[7; 4096]).unwrap();
+        temp.child("test.db-wal").write_binary(&[7; 4096]).unwrap();
+
+        let c = connect_to_nx_db(
+            temp.path().display().to_string(),
+            String::from("0.0.0"),
+            Some(String::from("test")),
+            None,
+        )
+        .unwrap();
+
+        assert!(c.table_exists("metadata").unwrap());
+        let version: Option<String> = c
+            .query_row("SELECT value FROM metadata WHERE key = 'NX_VERSION'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version.as_deref(), Some("0.0.0"));
+    }
+}
@@ -1,3 +1,4 @@
+use std::io::Read;
 use std::path::Path;
 
 use tracing::trace;
@@ -7,6 +8,21 @@ pub fn hash(content: &[u8]) -> String {
     xxh3::xxh3_64(content).to_string()
 }
 
+/// Hashes everything read from `reader` without holding it in memory.
+/// The result is the same as hashing the contents with `hash`.
+pub fn hash_reader<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = xxh3::Xxh3::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.digest().to_string())
+}
+
 #[napi]
 pub fn hash_array(input: Vec<String>) -> String {
     let joined = input.join(",");
@@ -36,7 +52,7 @@ pub fn hash_file_path<P: AsRef<Path>>(path: P) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    use crate::native::hasher::hash_file;
+    use crate::native::hasher::{hash, hash_file, hash_reader};
     use assert_fs::prelude::*;
     use assert_fs::TempDir;
 
@@ -72,4 +88,14 @@ mod tests {
 
         assert_eq!(content.unwrap(), "6193209363630369380");
     }
+
+    #[test]
+    fn it_hashes_a_reader_like_its_contents() {
+        let content = "content".repeat(20_000);
+        assert_eq!(
+            hash_reader(content.as_bytes()).unwrap(),
+            hash(content.as_bytes())
+        );
+        assert_eq!(hash_reader(&[][..]).unwrap(), hash(&[]));
+    }
 }
@@ -28,21 +28,95 @@ export declare class ImportResult {
 
 export declare class NxCache {
   cacheDirectory: string
-  constructor(workspaceRoot: string, cachePath: string, dbConnection: ExternalObject<NxDbConnection>, linkTaskDetails?: boolean | undefined | null)
+  constructor(workspaceRoot: string, cachePath: string, dbConnection: ExternalObject<NxDbConnection>, linkTaskDetails?: boolean | undefined | null, options?: NxCacheOptions | undefined | null)
   get(hash: string): CachedResult | null
+  /**
+   * Looks up many entries with a single query. Returns the results of the entries which were found.
+   * Terminal outputs are only read with `include_terminal_output`, and are empty otherwise.
+   * Without them, entries which cannot be decrypted are only detected when they are restored.
+   */
+  getMany(hashes: Array<string>, includeTerminalOutput?: boolean | undefined | null): Record<string, CachedResult>
+  /**
+   * Returns the given hashes which are in the cache or in one of its layers,
+   * without reading the entries or marking them as accessed
+   */
+  containsMany(hashes: Array<string>): Array<string>
   put(hash: string, terminalOutput: string, outputs: Array<string>, code: number): void
   applyRemoteCacheResults(hash: string, result: CachedResult): void
+  /**
+   * Downloads the entries which are missing from the local cache from the remote cache.
+   * Returns the results of the entries which were found in the remote cache.
+   */
+  retrieveFromRemote(hashes: Array<string>): Record<string, CachedResult>
+  /**
+   * Uploads the given entries to the remote cache, skipping the ones it already contains.
+   * Returns the hashes which were uploaded.
+   */
+  storeToRemote(hashes: Array<string>): Array<string>
+  /**
+   * Writes the entries matching the filter into a single bundle file which can be
+   * imported into the cache of another machine. Returns the hashes which were exported.
+   */
+  exportBundle(bundlePath: string, filter?: CacheExportFilter | undefined | null): Array<string>
+  /**
+   * Imports the entries of a bundle written by `export_bundle`, along with their task details.
+   * Entries which are already in the cache are skipped. Returns the hashes which were imported.
+   */
+  importBundle(bundlePath: string): Array<string>
   getTaskOutputsPath(hash: string): string
-  copyFilesFromCache(cachedResult: CachedResult, outputs: Array<string>): void
-  removeOldCacheRecords(): void
+  copyFilesFromCache(cachedResult: CachedResult, outputs: Array<string>): CacheRestoreReport
+  /**
+   * Removes the least recently accessed entries until the cache satisfies the given policy.
+   * By default, entries which have not been accessed in the last 7 days are removed.
+   * Pinned entries are never removed, but they count towards the size and number of entries.
+   */
+  removeOldCacheRecords(policy?: CacheEvictionPolicy | undefined | null): CacheEvictionReport
+  /**
+   * Pins an entry so that it is never evicted, and adds the given tags to it.
+   * Returns false if the cache does not contain the entry.
+   */
+  pin(hash: string, tags?: Array<string> | undefined | null): boolean
+  /** Unpins an entry, keeping its tags. Returns false if the entry was not pinned. */
+  unpin(hash: string): boolean
+  /** Unpins every entry with the given tag. Returns the hashes which were unpinned. */
+  unpinByTag(tag: string): Array<string>
+  /** Returns every entry with the given tag, pinned or not */
+  getEntriesByTag(tag: string): Array<TaggedCacheEntry>
+  /** Returns the size and contents of the cache, along with its hit ratio */
+  getStats(options?: CacheStatsOptions | undefined | null): CacheStats
+  /**
+   * Reconciles the records of the cache with the cache directory and the blob store,
+   * and reports every mismatch between them. With `repair`, records and files which
+   * cannot be restored are removed and the references of blobs are recounted.
+   * Other processes should not write to the cache while it is being checked.
+   */
+  fsck(repair?: boolean | undefined | null): CacheFsckReport
   checkCacheFsInSync(): boolean
 }
 
 export declare class NxTaskHistory {
   constructor(db: ExternalObject<NxDbConnection>)
   recordTaskRuns(taskRuns: Array<TaskRun>): void
+  /** Returns the task runs matching the filters of the query, with the details of their tasks */
+  queryTaskRuns(query: TaskRunQuery): TaskRunQueryResult
   getFlakyTasks(hashes: Array<string>): Array<string>
   getEstimatedTaskTimings(targets: Array<TaskTarget>): Record<string, number>
+  /**
+   * Scores how flaky targets are, from the runs inside the window which
+   * succeeded right after a failed run of the same hash.
+   * Targets which were not flaky are left out, and the flakiest come first.
+   */
+  getFlakyTargets(options?: FlakyTargetOptions | undefined | null): Array<FlakyTarget>
+  /**
+   * Returns the duration statistics of the given targets.
+   * Targets without any matching runs are left out.
+   */
+  getTaskTimingStats(targets: Array<TaskTarget>, options?: TaskTimingOptions | undefined | null): Record<string, TaskTimingStats>
+  /**
+   * Estimates how long the task graph takes to run with the given parallelism,
+   * from the median duration of the recent runs of every task.
+   */
+  estimateTaskGraph(taskGraph: TaskGraph, parallel: number, options?: TaskGraphEstimateOptions | undefined | null): TaskGraphEstimate
 }
 
 export declare class RustPseudoTerminal {
@@ -91,16 +165,180 @@ export declare class WorkspaceContext {
   getFilesInDirectory(directory: string): Array<string>
 }
 
+export declare const enum CacheAccessMode {
+  /** Entries are read from and written to the cache */
+  ReadWrite = 'ReadWrite',
+  /** Entries are only read from the cache. Storing entries does nothing. */
+  ReadOnly = 'ReadOnly',
+  /** Entries are only written to the cache. Reading entries always misses. */
+  WriteOnly = 'WriteOnly'
+}
+
 export interface CachedResult {
   code: number
   terminalOutput: string
   outputsPath: string
 }
 
-export declare export function connectToNxDb(cacheDir: string, nxVersion: string, dbName?: string | undefined | null): ExternalObject<NxDbConnection>
+export declare const enum CacheEntryFormat {
+  /** Entries are stored as directories which share their files through the blob store */
+  Directory = 'Directory',
+  /** Entries are stored as single compressed archives */
+  Archive = 'Archive'
+}
+
+export interface CacheEntryInfo {
+  hash: string
+  project?: string
+  target?: string
+  size: number
+  /** Milliseconds since the epoch */
+  createdAt: number
+  /** Milliseconds since the epoch */
+  accessedAt: number
+}
+
+export interface CacheEvictionPolicy {
+  /** The maximum total size of the cache in bytes */
+  maxCacheSize?: number
+  /** The maximum number of entries in the cache */
+  maxEntries?: number
+  /** The maximum number of days since an entry was last accessed */
+  maxAgeDays?: number
+}
+
+export interface CacheEvictionReport {
+  entriesRemoved: number
+  bytesFreed: number
+}
+
+export interface CacheExportFilter {
+  /** Only export entries of tasks of these projects */
+  projects?: Array<string>
+  /** Only export entries of tasks of these targets */
+  targets?: Array<string>
+  /** Only export entries which were created in the last number of days */
+  maxAgeDays?: number
+  /** Only export these entries */
+  hashes?: Array<string>
+}
+
+export interface CacheFsckReport {
+  /** Records whose directory or archive is missing from the cache directory */
+  missingEntries: Array<string>
+  /** Directories and archives in the cache directory which have no record */
+  untrackedEntries: Array<string>
+  /** Terminal outputs which have no record */
+  orphanedTerminalOutputs: Array<string>
+  /** Records whose task details are missing */
+  missingTaskDetails: Array<string>
+  /** Blobs whose reference count does not match the entries which contain them */
+  mismatchedBlobReferences: Array<string>
+  /** Files in the blob store which are not contained in any entry */
+  orphanedBlobs: Array<string>
+  /** Whether the mismatches were repaired */
+  repaired: boolean
+}
+
+export interface CacheLayerOptions {
+  /** A directory holding entries as archives, such as a network drive or a directory baked into a CI image */
+  path: string
+  /** Entries are only read from read-only layers. Defaults to false. */
+  readOnly?: boolean
+}
+
+export interface CacheRestoreReport {
+  /** Paths which were written to the workspace */
+  written: Array<string>
+  /** Paths which were removed from the workspace */
+  removed: Array<string>
+  /** The number of files which already matched the cache and were left untouched */
+  unchanged: number
+}
+
+export interface CacheStats {
+  totalSize: number
+  entryCount: number
+  /**
+   * Entries and bytes per project and target, largest first.
+   * Entries without task details are not included.
+   */
+  usage: Array<CacheTargetUsage>
+  oldestEntry?: CacheEntryInfo
+  newestEntry?: CacheEntryInfo
+  localHits: number
+  remoteHits: number
+  misses: number
+  /** The share of task runs which were replayed from the cache, between 0 and 1 */
+  hitRatio: number
+}
+
+export interface CacheStatsOptions {
+  /**
+   * Only count the task runs of the last number of days in the hit ratio.
+   * Defaults to every recorded task run.
+   */
+  windowDays?: number
+}
+
+export interface CacheTargetUsage {
+  project: string
+  target: string
+  entries: number
+  size: number
+}
+
+/**
+ * Connects to the database of the workspace, or to a new in-memory database.
+ * Read-only connections never create the database or its lock file.
+ */
+export declare export function connectToNxDb(cacheDir: string, nxVersion: string, dbName?: string | undefined | null, mode?: DbOpenMode | undefined | null): ExternalObject<NxDbConnection>
 
 export declare export function copy(src: string, dest: string): void
 
+export declare const enum CopyStrategy {
+  /** Files are copied */
+  Copy = 'Copy',
+  /** Files are cloned with copy-on-write when the filesystem supports it, and copied otherwise */
+  Reflink = 'Reflink',
+  /**
+   * Files are hardlinked to the cache, which makes restored outputs read-only.
+   * Linked files are shared by every entry with the same contents,
+   * so the mode and modification time of each entry are not restored.
+   */
+  Hardlink = 'Hardlink'
+}
+
+export interface DbMaintenanceOptions {
+  /** Task runs which ended more than this number of days ago are removed. Defaults to 30. */
+  maxTaskRunAgeDays?: number
+  /** The maximum number of task runs to keep. The most recent ones are kept. */
+  maxTaskRuns?: number
+  /** Rewrite the database to give the space of removed rows back to the filesystem. Defaults to true. */
+  vacuum?: boolean
+}
+
+export interface DbMaintenanceReport {
+  taskRunsRemoved: number
+  /** Task details which were no longer referenced by task runs or cache entries */
+  taskDetailsRemoved: number
+  /** The problems found by the integrity check. The database is recreated when there are any. */
+  integrityErrors: Array<string>
+  /** Whether the database was recreated because it is corrupted */
+  rebuilt: boolean
+  /** How much the files of the database shrank */
+  bytesFreed: number
+}
+
+export declare const enum DbOpenMode {
+  /** The database file is created if it does not exist, and migrated to the schema of this version of Nx */
+  ReadWrite = 'ReadWrite',
+  /** The database file is only read. It is never created or migrated, and nothing is written to it. */
+  ReadOnly = 'ReadOnly',
+  /** A new database is created in memory, and is gone once the connection is closed */
+  InMemory = 'InMemory'
+}
+
 export interface DepsOutputsInput {
   dependentTasksOutputFiles: string
   transitive?: boolean
@@ -144,6 +382,31 @@ export interface FileSetInput {
 
 export declare export function findImports(projectFileMap: Record<string, Array<string>>): Array<ImportResult>
 
+export interface FlakyTarget {
+  /** The target as project:target[:configuration] */
+  target: string
+  /** The share of runs which succeeded right after a failed run of the same hash, between 0 and 1 */
+  score: number
+  flakyRuns: number
+  totalRuns: number
+  /** When the last run which succeeded after a failure ended, in milliseconds since the epoch */
+  lastFlakyAt: number
+  /** Hashes which failed and then succeeded, most recent first */
+  exampleHashes: Array<string>
+}
+
+export interface FlakyTargetOptions {
+  /** Only score these targets. Defaults to every target with recorded runs. */
+  targets?: Array<TaskTarget>
+  /**
+   * Only use the runs which ended in the last number of days. Defaults to 14.
+   * Runs are still compared with the previous run of their hash when it ended before the window.
+   */
+  windowDays?: number
+  /** The maximum number of example hashes per target. Defaults to 5. */
+  maxExampleHashes?: number
+}
+
 export declare export function getBinaryTarget(): string
 
 /**
@@ -182,6 +445,51 @@ export interface InputsInput {
 
 export const IS_WASM: boolean
 
+/**
+ * Removes old task runs, gives unused space back to the filesystem and checks the integrity of the database.
+ * A corrupted database is recreated, so this should run before any connection to the database is opened.
+ * Databases which cannot be read at all are also recreated by `connectToNxDb`.
+ */
+export declare export function maintainNxDb(cacheDir: string, nxVersion: string, dbName?: string | undefined | null, options?: DbMaintenanceOptions | undefined | null): DbMaintenanceReport
+
+export interface NxCacheOptions {
+  /**
+   * How new entries are written to the cache directory.
+   * Entries are always read in the format they were written in.
+   */
+  entryFormat?: CacheEntryFormat
+  /**
+   * How files are copied between the workspace and entries stored as directories.
+   * Entries stored as archives are always extracted.
+   */
+  copyStrategy?: CopyStrategy
+  /** How outputs are restored into the workspace */
+  restoreMode?: RestoreMode
+  /**
+   * Check the files of entries against the hashes recorded when they were stored.
+   * Entries which fail the check are treated as a miss and removed.
+   */
+  verifyIntegrity?: boolean
+  /** Share entries with a remote cache through `retrieveFromRemote` and `storeToRemote` */
+  remoteCache?: RemoteCacheOptions
+  /**
+   * Whether entries are read from and written to the cache, locally and remotely.
+   * Defaults to the value of `NX_CACHE_ACCESS_MODE` (`read-write`, `read-only` or `write-only`).
+   */
+  accessMode?: CacheAccessMode
+  /**
+   * Directories which are looked up in order when an entry is missing from the cache directory.
+   * Entries found in a layer are copied into the cache directory, and new entries are copied into every writable layer.
+   */
+  layers?: Array<CacheLayerOptions>
+  /**
+   * Encrypt terminal outputs and archives with the key in `NX_CLOUD_ENCRYPTION_KEY`.
+   * Entries which cannot be decrypted with the key are treated as a miss.
+   * New entries are always stored as archives, since the files of directories cannot be encrypted.
+   */
+  encrypt?: boolean
+}
+
 /** Stripped version of the NxJson interface for use in rust */
 export interface NxJson {
   namedInputs?: Record<string, Array<JsInputs>>
@@ -212,12 +520,42 @@ export interface ProjectGraph {
   externalNodes: Record<string, ExternalNode>
 }
 
+export interface RemoteCacheOptions {
+  /** The url of an HTTP cache server, or the path of a directory shared between machines */
+  url: string
+  /** Sent as a bearer token to HTTP cache servers */
+  token?: string
+  /** How many times a failed request is retried. Defaults to 3. */
+  maxRetries?: number
+  /** How many requests can be in flight at the same time. Defaults to 8. */
+  maxConcurrency?: number
+  /** How many seconds a request can wait for the HTTP cache server before it fails. Defaults to 30. */
+  timeoutSecs?: number
+}
+
 export declare export function remove(src: string): void
 
+export declare const enum RestoreMode {
+  /** The outputs are removed and restored completely */
+  Replace = 'Replace',
+  /**
+   * Only the files which differ from the cache are rewritten,
+   * and only the files which are not in the cache are removed
+   */
+  Differential = 'Differential'
+}
+
 export interface RuntimeInput {
   runtime: string
 }
 
+export interface TaggedCacheEntry {
+  hash: string
+  /** Pinned entries are never removed by `remove_old_cache_records` */
+  pinned: boolean
+  tags: Array<string>
+}
+
 export interface Target {
   executor?: string
   inputs?: Array<JsInputs>
@@ -234,12 +572,52 @@ export interface Task {
   projectRoot?: string
 }
 
+export interface TaskEstimate {
+  /** The estimated duration of the task in milliseconds */
+  duration: number
+  /** The earliest the task can start, if there was no limit on parallelism */
+  earliestStart: number
+  earliestFinish: number
+  /**
+   * How long the task can be delayed without delaying the task graph.
+   * Tasks on the critical path have none.
+   */
+  slack: number
+  /** When the task is expected to start with the given parallelism */
+  expectedStart: number
+  expectedFinish: number
+}
+
 export interface TaskGraph {
   roots: Array<string>
   tasks: Record<string, Task>
   dependencies: Record<string, Array<string>>
 }
 
+export interface TaskGraphEstimate {
+  /**
+   * The longest chain of dependent tasks, from the first to the last task.
+   * The task graph cannot finish faster than it, no matter the parallelism.
+   */
+  criticalPath: Array<string>
+  criticalPathDuration: number
+  /** The expected wall-clock time to run the task graph with the given parallelism */
+  expectedDuration: number
+  tasks: Record<string, TaskEstimate>
+  /** Tasks which were estimated with the default duration, as they have no history */
+  tasksWithoutHistory: Array<string>
+}
+
+export interface TaskGraphEstimateOptions {
+  /** Which runs the median duration of every task is estimated from */
+  timing?: TaskTimingOptions
+  /**
+   * The duration of tasks without history, in milliseconds.
+   * Defaults to the average estimate of the tasks with history.
+   */
+  defaultDuration?: number
+}
+
 export interface TaskRun {
   hash: string
   status: string
@@ -248,12 +626,77 @@ export interface TaskRun {
   end: number
 }
 
+export interface TaskRunQuery {
+  project?: string
+  target?: string
+  configuration?: string
+  /** Only runs of targets without a configuration, which `configuration` cannot match */
+  withoutConfiguration?: boolean
+  status?: string
+  hash?: string
+  /** Only runs which started at or after this time, in milliseconds since the epoch */
+  startedAfter?: number
+  /** Only runs which started before this time, in milliseconds since the epoch */
+  startedBefore?: number
+  /** Defaults to sorting by start */
+  sortBy?: TaskRunSortField
+  /** Defaults to true, which returns the most recent or longest runs first */
+  descending?: boolean
+  /** The maximum number of runs to return. Defaults to 100. */
+  limit?: number
+  /** The number of matching runs to skip */
+  offset?: number
+}
+
+export interface TaskRunQueryResult {
+  runs: Array<TaskRunRecord>
+  /** The number of runs matching the filters, regardless of the limit and offset */
+  total: number
+}
+
+export interface TaskRunRecord {
+  hash: string
+  project: string
+  target: string
+  configuration?: string
+  status: string
+  code: number
+  start: number
+  end: number
+}
+
+export declare const enum TaskRunSortField {
+  Start = 'Start',
+  End = 'End',
+  Duration = 'Duration'
+}
+
 export interface TaskTarget {
   project: string
   target: string
   configuration?: string
 }
 
+export interface TaskTimingOptions {
+  /** Only use the most recent number of runs of every target */
+  lastRuns?: number
+  /** Only use the runs which ended in the last number of days */
+  lastDays?: number
+  /** Only use runs which succeeded. Defaults to false. */
+  successfulOnly?: boolean
+}
+
+/** Statistics of the durations of the runs of a target, in milliseconds */
+export interface TaskTimingStats {
+  count: number
+  mean: number
+  p50: number
+  p90: number
+  max: number
+  /** The population standard deviation */
+  stdDev: number
+}
+
 export declare export function testOnlyTransferFileMap(projectFiles: Record<string, Array<FileData>>, nonProjectFiles: Array<FileData>): NxWorkspaceFilesExternals
 
 /**
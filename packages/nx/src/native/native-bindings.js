@@ -371,8 +371,12 @@ module.exports.TaskDetails = nativeBinding.TaskDetails
 module.exports.TaskHasher = nativeBinding.TaskHasher
 module.exports.Watcher = nativeBinding.Watcher
 module.exports.WorkspaceContext = nativeBinding.WorkspaceContext
+module.exports.CacheAccessMode = nativeBinding.CacheAccessMode
+module.exports.CacheEntryFormat = nativeBinding.CacheEntryFormat
 module.exports.connectToNxDb = nativeBinding.connectToNxDb
 module.exports.copy = nativeBinding.copy
+module.exports.CopyStrategy = nativeBinding.CopyStrategy
+module.exports.DbOpenMode = nativeBinding.DbOpenMode
 module.exports.EventType = nativeBinding.EventType
 module.exports.expandOutputs = nativeBinding.expandOutputs
 module.exports.findImports = nativeBinding.findImports
@@ -382,7 +386,10 @@ module.exports.getTransformableOutputs = nativeBinding.getTransformableOutputs
 module.exports.hashArray = nativeBinding.hashArray
 module.exports.hashFile = nativeBinding.hashFile
 module.exports.IS_WASM = nativeBinding.IS_WASM
+module.exports.maintainNxDb = nativeBinding.maintainNxDb
 module.exports.remove = nativeBinding.remove
+module.exports.RestoreMode = nativeBinding.RestoreMode
+module.exports.TaskRunSortField = nativeBinding.TaskRunSortField
 module.exports.testOnlyTransferFileMap = nativeBinding.testOnlyTransferFileMap
 module.exports.transferProjectGraph = nativeBinding.transferProjectGraph
 module.exports.validateOutputs = nativeBinding.validateOutputs
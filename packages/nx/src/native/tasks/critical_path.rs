@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+
+use crate::native::tasks::types::TaskGraph;
+
+#[napi(object)]
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct TaskEstimate {
+    /// The estimated duration of the task in milliseconds
+    pub duration: f64,
+    /// The earliest the task can start, if there was no limit on parallelism
+    pub earliest_start: f64,
+    pub earliest_finish: f64,
+    /// How long the task can be delayed without delaying the task graph.
+    /// Tasks on the critical path have none.
+    pub slack: f64,
+    /// When the task is expected to start with the given parallelism
+    pub expected_start: f64,
+    pub expected_finish: f64,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct TaskGraphEstimate {
+    /// The longest chain of dependent tasks, from the first to the last task.
+    /// The task graph cannot finish faster than it, no matter the parallelism.
+    pub critical_path: Vec<String>,
+    pub critical_path_duration: f64,
+    /// The expected wall-clock time to run the task graph with the given parallelism
+    pub expected_duration: f64,
+    pub tasks: HashMap<String, TaskEstimate>,
+    /// Tasks which were estimated with the default duration, as they have no history
+    pub tasks_without_history: Vec<String>,
+}
+
+/// Computes the critical path of the task graph and simulates running it with the given parallelism.
+///
+/// Tasks without a duration are estimated with the default duration, which defaults
+/// to the average duration of the other tasks.
+pub fn estimate_task_graph(
+    task_graph: &TaskGraph,
+    durations: &HashMap<String, f64>,
+    parallel: u32,
+    default_duration: Option<f64>,
+) -> anyhow::Result<TaskGraphEstimate> {
+    let default_duration = default_duration.unwrap_or_else(|| {
+        if durations.is_empty() {
+            0.0
+        } else {
+            durations.values().sum::<f64>() / durations.len() as f64
+        }
+    });
+
+    let mut task_ids = task_graph.tasks.keys().collect::<Vec<_>>();
+    task_ids.sort();
+    let mut tasks_without_history = vec![];
+    let duration = task_ids
+        .iter()
+        .map(|&id| {
+            let duration = durations.get(id).copied().unwrap_or_else(|| {
+                tasks_without_history.push(id.clone());
+                default_duration
+            });
+            (id, duration)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let dependencies = task_ids
+        .iter()
+        .map(|&id| {
+            let dependencies = task_graph
+                .dependencies
+                .get(id)
+                .into_iter()
+                .flatten()
+                .filter(|dependency| task_graph.tasks.contains_key(*dependency))
+                .collect::<Vec<_>>();
+            (id, dependencies)
+        })
+        .collect::<HashMap<_, _>>();
+    let mut dependents: HashMap<&String, Vec<&String>> = HashMap::new();
+    for (&id, task_dependencies) in &dependencies {
+        for &dependency in task_dependencies {
+            dependents.entry(dependency).or_default().push(id);
+        }
+    }
+    let order = topological_order(&task_ids, &dependencies, &dependents)?;
+
+    // Forward pass: the earliest every task can start and finish
+    let mut earliest_finish: HashMap<&String, f64> = HashMap::new();
+    for &id in &order {
+        let start = dependencies[id]
+            .iter()
+            .map(|dependency| earliest_finish[dependency])
+            .fold(0.0, f64::max);
+        earliest_finish.insert(id, start + duration[id]);
+    }
+    let critical_path_duration = earliest_finish.values().copied().fold(0.0, f64::max);
+
+    // Backward pass: the latest every task can finish without delaying the task graph
+    let mut latest_finish: HashMap<&String, f64> = HashMap::new();
+    for &id in order.iter().rev() {
+        let finish = dependents
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|dependent| latest_finish[dependent] - duration[dependent])
+            .fold(critical_path_duration, f64::min);
+        latest_finish.insert(id, finish);
+    }
+
+    let critical_path = critical_path(&order, &dependencies, &duration, &earliest_finish);
+    let expected_start = simulate(
+        &order,
+        &dependencies,
+        &dependents,
+        &duration,
+        &latest_finish,
+        parallel,
+    );
+    let expected_duration = order
+        .iter()
+        .map(|id| expected_start[id] + duration[id])
+        .fold(0.0, f64::max);
+
+    let tasks = order
+        .iter()
+        .map(|&id| {
+            let estimate = TaskEstimate {
+                duration: duration[id],
+                earliest_start: earliest_finish[id] - duration[id],
+                earliest_finish: earliest_finish[id],
+                slack: latest_finish[id] - earliest_finish[id],
+                expected_start: expected_start[id],
+                expected_finish: expected_start[id] + duration[id],
+            };
+            (id.clone(), estimate)
+        })
+        .collect();
+
+    Ok(TaskGraphEstimate {
+        critical_path,
+        critical_path_duration,
+        expected_duration,
+        tasks,
+        tasks_without_history,
+    })
+}
+
+/// Orders the tasks so that every task comes after its dependencies
+fn topological_order<'a>(
+    task_ids: &[&'a String],
+    dependencies: &HashMap<&'a String, Vec<&'a String>>,
+    dependents: &HashMap<&'a String, Vec<&'a String>>,
+) -> anyhow::Result<Vec<&'a String>> {
+    let mut remaining = task_ids
+        .iter()
+        .map(|&id| (id, dependencies[id].len()))
+        .collect::<HashMap<_, _>>();
+    let mut order = task_ids
+        .iter()
+        .copied()
+        .filter(|id| remaining[id] == 0)
+        .collect::<Vec<_>>();
+    let mut i = 0;
+    while i < order.len() {
+        for &dependent in dependents.get(order[i]).into_iter().flatten() {
+            let count = remaining.get_mut(dependent).expect("dependents are tasks");
+            *count -= 1;
+            if *count == 0 {
+                order.push(dependent);
+            }
+        }
+        i += 1;
+    }
+
+    if order.len() < task_ids.len() {
+        let mut circular = remaining
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(id, _)| id.as_str())
+            .collect::<Vec<_>>();
+        circular.sort();
+        anyhow::bail!(
+            "The task graph has circular dependencies between: {}",
+            circular.join(", ")
+        );
+    }
+    Ok(order)
+}
+
+/// Walks back from the task which finishes last, through the dependencies which finish last
+fn critical_path(
+    order: &[&String],
+    dependencies: &HashMap<&String, Vec<&String>>,
+    duration: &HashMap<&String, f64>,
+    earliest_finish: &HashMap<&String, f64>,
+) -> Vec<String> {
+    let latest = |ids: &mut dyn Iterator<Item = &String>| {
+        ids.max_by(|a, b| {
+            earliest_finish[a]
+                .total_cmp(&earliest_finish[b])
+                .then(b.cmp(a))
+        })
+        .cloned()
+    };
+
+    let mut path = vec![];
+    let mut current = latest(&mut order.iter().copied());
+    while let Some(id) = current {
+        let start = earliest_finish[&id] - duration[&id];
+        current = latest(
+            &mut dependencies[&id]
+                .iter()
+                .copied()
+                .filter(|dependency| earliest_finish[dependency] >= start),
+        );
+        path.push(id);
+    }
+    path.reverse();
+    path
+}
+
+/// Simulates running the tasks with a limited number of workers, and returns when every task starts.
+/// Of the tasks which are ready, the ones with the longest path to the end of the task graph are run first.
+fn simulate<'a>(
+    order: &[&'a String],
+    dependencies: &HashMap<&'a String, Vec<&'a String>>,
+    dependents: &HashMap<&'a String, Vec<&'a String>>,
+    duration: &HashMap<&'a String, f64>,
+    latest_finish: &HashMap<&'a String, f64>,
+    parallel: u32,
+) -> HashMap<&'a String, f64> {
+    let workers = parallel.max(1) as usize;
+    let latest_start = |id: &String| latest_finish[id] - duration[id];
+
+    let mut remaining = order
+        .iter()
+        .map(|&id| (id, dependencies[id].len()))
+        .collect::<HashMap<_, _>>();
+    let mut ready = order
+        .iter()
+        .copied()
+        .filter(|id| remaining[id] == 0)
+        .collect::<Vec<_>>();
+    let mut running: Vec<(f64, &String)> = vec![];
+    let mut start = HashMap::new();
+    let mut time = 0.0;
+
+    loop {
+        while running.len() < workers && !ready.is_empty() {
+            let (i, _) = ready
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| latest_start(a).total_cmp(&latest_start(b)).then(a.cmp(b)))
+                .expect("ready is not empty");
+            let id = ready.swap_remove(i);
+            start.insert(id, time);
+            running.push((time + duration[id], id));
+        }
+        let Some(next) = running.iter().map(|(finish, _)| *finish).reduce(f64::min) else {
+            break;
+        };
+
+        time = next;
+        let (finished, still_running) = running
+            .into_iter()
+            .partition::<Vec<_>, _>(|(finish, _)| *finish <= time);
+        running = still_running;
+        for (_, id) in finished {
+            for &dependent in dependents.get(id).into_iter().flatten() {
+                let count = remaining.get_mut(dependent).expect("dependents are tasks");
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+    }
+    start
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::native::tasks::types::{Task, TaskTarget};
+
+    fn task_graph(dependencies: &[(&str, &[&str])]) -> TaskGraph {
+        let task = |id: &str| Task {
+            id: id.to_string(),
+            target: TaskTarget {
+                project: id.to_string(),
+                target: String::from("build"),
+                configuration: None,
+            },
+            outputs: vec![],
+            project_root: None,
+        };
+        TaskGraph {
+            roots: vec![],
+            tasks: dependencies
+                .iter()
+                .map(|(id, _)| (id.to_string(), task(id)))
+                .collect(),
+            dependencies: dependencies
+                .iter()
+                .map(|(id, dependencies)| {
+                    let dependencies = dependencies.iter().map(|d| d.to_string()).collect();
+                    (id.to_string(), dependencies)
+                })
+                .collect(),
+        }
+    }
+
+    fn durations(durations: &[(&str, f64)]) -> HashMap<String, f64> {
+        durations
+            .iter()
+            .map(|(id, duration)| (id.to_string(), *duration))
+            .collect()
+    }
+
+    #[test]
+    fn should_compute_the_critical_path() {
+        // app depends on a slow and a fast library, which both depend on utils
+        let graph = task_graph(&[
+            ("utils", &[]),
+            ("slow", &["utils"]),
+            ("fast", &["utils"]),
+            ("app", &["slow", "fast"]),
+        ]);
+        let durations = durations(&[
+            ("utils", 10.0),
+            ("slow", 50.0),
+            ("fast", 20.0),
+            ("app", 5.0),
+        ]);
+
+        let estimate = estimate_task_graph(&graph, &durations, 2, None).unwrap();
+
+        assert_eq!(estimate.critical_path, vec!["utils", "slow", "app"]);
+        assert_eq!(estimate.critical_path_duration, 65.0);
+        assert_eq!(estimate.expected_duration, 65.0);
+        assert!(estimate.tasks_without_history.is_empty());
+        assert_eq!(
+            estimate.tasks["fast"],
+            TaskEstimate {
+                duration: 20.0,
+                earliest_start: 10.0,
+                earliest_finish: 30.0,
+                slack: 30.0,
+                expected_start: 10.0,
+                expected_finish: 30.0,
+            }
+        );
+        assert_eq!(estimate.tasks["app"].slack, 0.0);
+
+        // Without parallelism the libraries run one after another
+        let estimate = estimate_task_graph(&graph, &durations, 1, None).unwrap();
+        assert_eq!(estimate.expected_duration, 85.0);
+        assert_eq!(estimate.tasks["slow"].expected_start, 10.0);
+        assert_eq!(estimate.tasks["fast"].expected_start, 60.0);
+    }
+
+    #[test]
+    fn should_run_tasks_on_the_critical_path_first() {
+        let graph = task_graph(&[("a", &[]), ("b", &[]), ("c", &[]), ("d", &["c"])]);
+        let durations = durations(&[("a", 10.0), ("b", 10.0), ("c", 10.0), ("d", 10.0)]);
+
+        let estimate = estimate_task_graph(&graph, &durations, 2, None).unwrap();
+
+        assert_eq!(estimate.tasks["c"].expected_start, 0.0);
+        assert_eq!(estimate.expected_duration, 20.0);
+    }
+
+    #[test]
+    fn should_estimate_tasks_without_history() {
+        let graph = task_graph(&[("a", &[]), ("b", &["a"]), ("c", &["b"])]);
+
+        let estimate =
+            estimate_task_graph(&graph, &durations(&[("a", 10.0), ("b", 30.0)]), 4, None).unwrap();
+        assert_eq!(estimate.tasks_without_history, vec!["c"]);
+        assert_eq!(estimate.tasks["c"].duration, 20.0);
+
+        let estimate = estimate_task_graph(&graph, &HashMap::new(), 4, Some(5.0)).unwrap();
+        assert_eq!(estimate.expected_duration, 15.0);
+    }
+
+    #[test]
+    fn should_fail_for_circular_dependencies() {
+        let graph = task_graph(&[("a", &["c"]), ("b", &["a"]), ("c", &["b"]), ("d", &[])]);
+        let error = estimate_task_graph(&graph, &HashMap::new(), 1, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The task graph has circular dependencies between: a, b, c"
+        );
+    }
+}
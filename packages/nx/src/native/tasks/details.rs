@@ -1,6 +1,7 @@
 use crate::native::db::connection::NxDbConnection;
 use napi::bindgen_prelude::*;
 use rusqlite::params;
+use tracing::trace;
 
 #[napi(object)]
 #[derive(Default, Clone)]
@@ -28,14 +29,14 @@ impl TaskDetails {
     }
 
     fn setup(&self) -> anyhow::Result<()> {
-        self.db.execute(
+        self.db.create_table(
+            "task_details",
             "CREATE TABLE IF NOT EXISTS task_details (
                 hash    TEXT PRIMARY KEY NOT NULL,
                 project  TEXT NOT NULL,
                 target  TEXT NOT NULL,
                 configuration  TEXT
             );",
-            params![],
         )?;
 
         Ok(())
@@ -43,14 +44,62 @@ impl TaskDetails {
 
     #[napi]
     pub fn record_task_details(&self, tasks: Vec<HashedTask>) -> anyhow::Result<()> {
-        for task in tasks.iter() {
-            self.db.execute(
+        if self.db.is_read_only() {
+            trace!("Recording task details skipped, the database is read-only");
+            return Ok(());
+        }
+        self.db.transaction(|tx| {
+            let mut statement = tx.prepare(
                 "INSERT OR REPLACE INTO task_details  (hash, project, target, configuration)
                     VALUES (?1, ?2, ?3, ?4)",
-                params![task.hash, task.project, task.target, task.configuration],
             )?;
+            for task in tasks.iter() {
+                statement.execute(params![
+                    task.hash,
+                    task.project,
+                    task.target,
+                    task.configuration
+                ])?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn hashed_task(hash: &str) -> HashedTask {
+        HashedTask {
+            hash: hash.to_string(),
+            project: String::from("proj"),
+            target: String::from("build"),
+            configuration: None,
         }
+    }
 
-        Ok(())
+    #[test]
+    fn should_not_record_part_of_a_failed_batch() {
+        let db = NxDbConnection::new(Connection::open_in_memory().unwrap());
+        let task_details = TaskDetails::new(External::new(db)).unwrap();
+        task_details
+            .db
+            .execute_batch(
+                "CREATE TRIGGER fail BEFORE INSERT ON task_details WHEN NEW.hash = 'fail'
+                    BEGIN SELECT RAISE(ABORT, 'fail'); END",
+            )
+            .unwrap();
+
+        let result =
+            task_details.record_task_details(vec![hashed_task("123"), hashed_task("fail")]);
+        assert!(result.is_err());
+
+        let count: Option<i64> = task_details
+            .db
+            .query_row("SELECT COUNT(*) FROM task_details", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, Some(0));
     }
 }
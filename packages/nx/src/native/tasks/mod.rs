@@ -1,3 +1,4 @@
+pub mod critical_path;
 mod dep_outputs;
 mod hash_planner;
 pub mod hashers;
@@ -1,10 +1,12 @@
 use crate::native::db::connection::NxDbConnection;
-use crate::native::tasks::types::TaskTarget;
+use crate::native::tasks::critical_path::{estimate_task_graph, TaskGraphEstimate};
+use crate::native::tasks::types::{TaskGraph, TaskTarget};
 use napi::bindgen_prelude::*;
 use rusqlite::vtab::array;
-use rusqlite::{params, types::Value};
+use rusqlite::{params, params_from_iter, types::Value};
 use std::collections::HashMap;
 use std::rc::Rc;
+use tracing::trace;
 
 #[napi(object)]
 pub struct TaskRun {
@@ -15,6 +17,121 @@ pub struct TaskRun {
     pub end: i64,
 }
 
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct TaskTimingOptions {
+    /// Only use the most recent number of runs of every target
+    pub last_runs: Option<u32>,
+    /// Only use the runs which ended in the last number of days
+    pub last_days: Option<u32>,
+    /// Only use runs which succeeded. Defaults to false.
+    pub successful_only: Option<bool>,
+}
+
+/// Statistics of the durations of the runs of a target, in milliseconds
+#[napi(object)]
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct TaskTimingStats {
+    pub count: u32,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub max: f64,
+    /// The population standard deviation
+    pub std_dev: f64,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct TaskGraphEstimateOptions {
+    /// Which runs the median duration of every task is estimated from
+    pub timing: Option<TaskTimingOptions>,
+    /// The duration of tasks without history, in milliseconds.
+    /// Defaults to the average estimate of the tasks with history.
+    pub default_duration: Option<f64>,
+}
+
+#[napi(object)]
+#[derive(Default, Clone)]
+pub struct FlakyTargetOptions {
+    /// Only score these targets. Defaults to every target with recorded runs.
+    pub targets: Option<Vec<TaskTarget>>,
+    /// Only use the runs which ended in the last number of days. Defaults to 14.
+    /// Runs are still compared with the previous run of their hash when it ended before the window.
+    pub window_days: Option<u32>,
+    /// The maximum number of example hashes per target. Defaults to 5.
+    pub max_example_hashes: Option<u32>,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct FlakyTarget {
+    /// The target as project:target[:configuration]
+    pub target: String,
+    /// The share of runs which succeeded right after a failed run of the same hash, between 0 and 1
+    pub score: f64,
+    pub flaky_runs: u32,
+    pub total_runs: u32,
+    /// When the last run which succeeded after a failure ended, in milliseconds since the epoch
+    pub last_flaky_at: i64,
+    /// Hashes which failed and then succeeded, most recent first
+    pub example_hashes: Vec<String>,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Default, PartialEq)]
+pub enum TaskRunSortField {
+    #[default]
+    Start,
+    End,
+    Duration,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct TaskRunQuery {
+    pub project: Option<String>,
+    pub target: Option<String>,
+    pub configuration: Option<String>,
+    /// Only runs of targets without a configuration, which `configuration` cannot match
+    pub without_configuration: Option<bool>,
+    pub status: Option<String>,
+    pub hash: Option<String>,
+    /// Only runs which started at or after this time, in milliseconds since the epoch
+    pub started_after: Option<i64>,
+    /// Only runs which started before this time, in milliseconds since the epoch
+    pub started_before: Option<i64>,
+    /// Defaults to sorting by start
+    pub sort_by: Option<TaskRunSortField>,
+    /// Defaults to true, which returns the most recent or longest runs first
+    pub descending: Option<bool>,
+    /// The maximum number of runs to return. Defaults to 100.
+    pub limit: Option<u32>,
+    /// The number of matching runs to skip
+    pub offset: Option<u32>,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct TaskRunRecord {
+    pub hash: String,
+    pub project: String,
+    pub target: String,
+    pub configuration: Option<String>,
+    pub status: String,
+    pub code: i16,
+    pub start: i64,
+    pub end: i64,
+}
+
+#[napi(object)]
+#[derive(Default, Clone, Debug)]
+pub struct TaskRunQueryResult {
+    pub runs: Vec<TaskRunRecord>,
+    /// The number of runs matching the filters, regardless of the limit and offset
+    pub total: u32,
+}
+
 #[napi]
 pub struct NxTaskHistory {
     db: External<NxDbConnection>,
@@ -33,10 +150,7 @@ impl NxTaskHistory {
 
     fn setup(&self) -> anyhow::Result<()> {
         array::load_module(&self.db.conn)?;
-        self.db
-            .execute_batch(
-                "
-            BEGIN IMMEDIATE;
+        let create_table = "
             CREATE TABLE IF NOT EXISTS task_history (
                 id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
                 hash TEXT NOT NULL,
@@ -46,33 +160,131 @@ impl NxTaskHistory {
                 end TIMESTAMP NOT NULL,
                 FOREIGN KEY (hash) REFERENCES task_details (hash)
             );
-            CREATE INDEX IF NOT EXISTS hash_idx ON task_history (hash);
-            COMMIT;
-            ",
-            )
-            .map_err(anyhow::Error::from)
+            ";
+        if self.db.is_read_only() {
+            return self.db.create_table("task_history", create_table);
+        }
+
+        self.db.transaction(|tx| {
+            tx.execute_batch(create_table)?;
+            tx.execute_batch("CREATE INDEX IF NOT EXISTS hash_idx ON task_history (hash);")?;
+            Ok(())
+        })
     }
 
     #[napi]
     pub fn record_task_runs(&self, task_runs: Vec<TaskRun>) -> anyhow::Result<()> {
-        for task_run in task_runs.iter() {
-            self.db
-                .execute(
-                    "
+        if self.db.is_read_only() {
+            trace!("Recording task runs skipped, the database is read-only");
+            return Ok(());
+        }
+        self.db.transaction(|tx| {
+            let mut statement = tx.prepare(
+                "
             INSERT INTO task_history
                 (hash, status, code, start, end)
                 VALUES (?1, ?2, ?3, ?4, ?5)",
-                    params![
-                        task_run.hash,
-                        task_run.status,
-                        task_run.code,
-                        task_run.start,
-                        task_run.end
-                    ],
-                )
-                .map_err(anyhow::Error::from)?;
+            )?;
+            for task_run in task_runs.iter() {
+                statement.execute(params![
+                    task_run.hash,
+                    task_run.status,
+                    task_run.code,
+                    task_run.start,
+                    task_run.end
+                ])?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the task runs matching the filters of the query, with the details of their tasks
+    #[napi]
+    pub fn query_task_runs(&self, query: TaskRunQuery) -> anyhow::Result<TaskRunQueryResult> {
+        let mut conditions = vec![];
+        let mut values: Vec<Value> = vec![];
+        for (column, value) in [
+            ("task_details.project", &query.project),
+            ("task_details.target", &query.target),
+            ("task_details.configuration", &query.configuration),
+            ("task_history.status", &query.status),
+            ("task_history.hash", &query.hash),
+        ] {
+            if let Some(value) = value {
+                conditions.push(format!("{} = ?", column));
+                values.push(Value::from(value.clone()));
+            }
+        }
+        if query.without_configuration.unwrap_or(false) {
+            conditions.push(String::from("task_details.configuration IS NULL"));
         }
-        Ok(())
+        if let Some(started_after) = query.started_after {
+            conditions.push(String::from("task_history.start >= ?"));
+            values.push(Value::from(started_after));
+        }
+        if let Some(started_before) = query.started_before {
+            conditions.push(String::from("task_history.start < ?"));
+            values.push(Value::from(started_before));
+        }
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let total = self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM task_history
+                        JOIN task_details ON task_history.hash = task_details.hash
+                        {}",
+                    filter
+                ),
+                params_from_iter(values.iter()),
+                |row| row.get(0),
+            )?
+            .unwrap_or(0);
+
+        let sort_column = match query.sort_by.unwrap_or_default() {
+            TaskRunSortField::Start => "task_history.start",
+            TaskRunSortField::End => "task_history.end",
+            TaskRunSortField::Duration => "task_history.end - task_history.start",
+        };
+        let direction = if query.descending.unwrap_or(true) {
+            "DESC"
+        } else {
+            "ASC"
+        };
+        values.push(Value::from(query.limit.unwrap_or(100)));
+        values.push(Value::from(query.offset.unwrap_or(0)));
+        let runs = self
+            .db
+            .prepare(&format!(
+                "SELECT
+                    task_history.hash, project, target, configuration, status, code, start, end
+                    FROM task_history
+                        JOIN task_details ON task_history.hash = task_details.hash
+                    {}
+                    ORDER BY {} {}, task_history.id {}
+                    LIMIT ? OFFSET ?",
+                filter, sort_column, direction, direction
+            ))?
+            .query_map(params_from_iter(values.iter()), |row| {
+                Ok(TaskRunRecord {
+                    hash: row.get(0)?,
+                    project: row.get(1)?,
+                    target: row.get(2)?,
+                    configuration: row.get(3)?,
+                    status: row.get(4)?,
+                    code: row.get(5)?,
+                    start: row.get(6)?,
+                    end: row.get(7)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(TaskRunQueryResult { runs, total })
     }
 
     #[napi]
@@ -105,14 +317,7 @@ impl NxTaskHistory {
         let values = Rc::new(
             targets
                 .iter()
-                .map(|t| {
-                    Value::from(match &t.configuration {
-                        Some(configuration) => {
-                            format!("{}:{}:{}", t.project, t.target, configuration)
-                        }
-                        _ => format!("{}:{}", t.project, t.target),
-                    })
-                })
+                .map(|t| Value::from(target_string(t)))
                 .collect::<Vec<Value>>(),
         );
 
@@ -137,4 +342,524 @@ impl NxTaskHistory {
             .map(|r| r.map_err(anyhow::Error::from))
             .collect()
     }
+
+    /// Scores how flaky targets are, from the runs inside the window which
+    /// succeeded right after a failed run of the same hash.
+    /// Targets which were not flaky are left out, and the flakiest come first.
+    #[napi]
+    pub fn get_flaky_targets(
+        &self,
+        options: Option<FlakyTargetOptions>,
+    ) -> anyhow::Result<Vec<FlakyTarget>> {
+        let options = options.unwrap_or_default();
+        let max_example_hashes = options.max_example_hashes.unwrap_or(5) as usize;
+        let values = Rc::new(
+            options
+                .targets
+                .iter()
+                .flatten()
+                .map(|t| Value::from(target_string(t)))
+                .collect::<Vec<Value>>(),
+        );
+
+        let mut flaky_targets: HashMap<String, FlakyTarget> = HashMap::new();
+        // The previous run of a hash may have ended before the window, so runs are only filtered
+        // by the window after they are compared with their previous run.
+        // Runs are ordered by the most recent first, which the example hashes rely on
+        self.db
+            .prepare(
+                "
+                SELECT target_string, hash, end,
+                    code = 0 AND previous_code IS NOT NULL AND previous_code <> 0 AS flaky
+                    FROM (
+                        SELECT
+                            CONCAT_WS(':', project, target, configuration) AS target_string,
+                            task_history.hash AS hash,
+                            code,
+                            end,
+                            task_history.id AS id,
+                            LAG(code) OVER (
+                                PARTITION BY task_history.hash ORDER BY start, task_history.id
+                            ) AS previous_code
+                            FROM task_history
+                                JOIN task_details ON task_history.hash = task_details.hash
+                            WHERE task_history.hash IN (
+                                SELECT hash FROM task_history
+                                    WHERE end >= (CAST(strftime('%s', 'now') AS INTEGER) - ?1 * 86400) * 1000
+                            )
+                    )
+                    WHERE end >= (CAST(strftime('%s', 'now') AS INTEGER) - ?1 * 86400) * 1000
+                        AND (?2 = 0 OR target_string IN rarray(?3))
+                    ORDER BY end DESC, id DESC
+                ",
+            )?
+            .query_map(
+                params![
+                    options.window_days.unwrap_or(14),
+                    options.targets.is_some(),
+                    values
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, bool>(3)?,
+                    ))
+                },
+            )?
+            .try_for_each(|r| {
+                let (target_string, hash, end, flaky) = r?;
+                let flaky_target =
+                    flaky_targets
+                        .entry(target_string.clone())
+                        .or_insert_with(|| FlakyTarget {
+                            target: target_string,
+                            ..Default::default()
+                        });
+                flaky_target.total_runs += 1;
+                if flaky {
+                    flaky_target.flaky_runs += 1;
+                    flaky_target.last_flaky_at = flaky_target.last_flaky_at.max(end);
+                    if flaky_target.example_hashes.len() < max_example_hashes
+                        && !flaky_target.example_hashes.contains(&hash)
+                    {
+                        flaky_target.example_hashes.push(hash);
+                    }
+                }
+                Ok::<_, anyhow::Error>(())
+            })?;
+
+        let mut flaky_targets = flaky_targets
+            .into_values()
+            .filter(|t| t.flaky_runs > 0)
+            .map(|t| FlakyTarget {
+                score: t.flaky_runs as f64 / t.total_runs as f64,
+                ..t
+            })
+            .collect::<Vec<_>>();
+        flaky_targets.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(b.last_flaky_at.cmp(&a.last_flaky_at))
+                .then(a.target.cmp(&b.target))
+        });
+        Ok(flaky_targets)
+    }
+
+    /// Returns the duration statistics of the given targets.
+    /// Targets without any matching runs are left out.
+    #[napi]
+    pub fn get_task_timing_stats(
+        &self,
+        targets: Vec<TaskTarget>,
+        options: Option<TaskTimingOptions>,
+    ) -> anyhow::Result<HashMap<String, TaskTimingStats>> {
+        let options = options.unwrap_or_default();
+        let values = Rc::new(
+            targets
+                .iter()
+                .map(|t| Value::from(target_string(t)))
+                .collect::<Vec<Value>>(),
+        );
+
+        let mut durations: HashMap<String, Vec<f64>> = HashMap::new();
+        // Task runs record their start and end in milliseconds since the epoch
+        self.db
+            .prepare(
+                "
+                SELECT target_string, duration FROM (
+                    SELECT
+                        CONCAT_WS(':', project, target, configuration) AS target_string,
+                        end - start AS duration,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY project, target, configuration
+                            ORDER BY end DESC, task_history.id DESC
+                        ) AS run_number
+                        FROM task_history
+                            JOIN task_details ON task_history.hash = task_details.hash
+                        WHERE target_string IN rarray(?1)
+                            AND (?2 IS NULL
+                                OR end >= (CAST(strftime('%s', 'now') AS INTEGER) - ?2 * 86400) * 1000)
+                            AND (?3 = 0 OR code = 0)
+                )
+                WHERE ?4 IS NULL OR run_number <= ?4
+                ",
+            )?
+            .query_map(
+                params![
+                    values,
+                    options.last_days,
+                    options.successful_only.unwrap_or(false),
+                    options.last_runs
+                ],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?)),
+            )?
+            .try_for_each(|r| {
+                let (target_string, duration) = r?;
+                durations.entry(target_string).or_default().push(duration);
+                Ok::<_, anyhow::Error>(())
+            })?;
+
+        Ok(durations
+            .into_iter()
+            .map(|(target_string, durations)| (target_string, timing_stats(durations)))
+            .collect())
+    }
+
+    /// Estimates how long the task graph takes to run with the given parallelism,
+    /// from the median duration of the recent runs of every task.
+    #[napi]
+    pub fn estimate_task_graph(
+        &self,
+        task_graph: TaskGraph,
+        parallel: u32,
+        options: Option<TaskGraphEstimateOptions>,
+    ) -> anyhow::Result<TaskGraphEstimate> {
+        let options = options.unwrap_or_default();
+        let targets = task_graph
+            .tasks
+            .values()
+            .map(|task| task.target.clone())
+            .collect();
+        let stats = self.get_task_timing_stats(targets, options.timing)?;
+        let durations = task_graph
+            .tasks
+            .iter()
+            .filter_map(|(id, task)| {
+                stats
+                    .get(&target_string(&task.target))
+                    .map(|stats| (id.clone(), stats.p50))
+            })
+            .collect();
+
+        estimate_task_graph(&task_graph, &durations, parallel, options.default_duration)
+    }
+}
+
+fn target_string(target: &TaskTarget) -> String {
+    match &target.configuration {
+        Some(configuration) => {
+            format!("{}:{}:{}", target.project, target.target, configuration)
+        }
+        _ => format!("{}:{}", target.project, target.target),
+    }
+}
+
+fn timing_stats(mut durations: Vec<f64>) -> TaskTimingStats {
+    if durations.is_empty() {
+        return TaskTimingStats::default();
+    }
+    durations.sort_by(|a, b| a.total_cmp(b));
+
+    let count = durations.len() as f64;
+    let mean = durations.iter().sum::<f64>() / count;
+    let variance = durations
+        .iter()
+        .map(|duration| (duration - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    TaskTimingStats {
+        count: durations.len() as u32,
+        mean,
+        p50: percentile(&durations, 0.5),
+        p90: percentile(&durations, 0.9),
+        max: durations[durations.len() - 1],
+        std_dev: variance.sqrt(),
+    }
+}
+
+/// Interpolates linearly between the closest ranks of sorted durations
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn task_run(hash: &str) -> TaskRun {
+        TaskRun {
+            hash: hash.to_string(),
+            status: String::from("success"),
+            code: 0,
+            start: 0,
+            end: 1,
+        }
+    }
+
+    #[test]
+    fn should_not_record_part_of_a_failed_batch() {
+        let db = NxDbConnection::new(Connection::open_in_memory().unwrap());
+        // Task details are set up by the cache before task runs are recorded
+        db.execute_batch(
+            "CREATE TABLE task_details (hash TEXT PRIMARY KEY NOT NULL);
+            INSERT INTO task_details (hash) VALUES ('123'), ('234'), ('345'), ('fail');",
+        )
+        .unwrap();
+        let task_history = NxTaskHistory::new(External::new(db)).unwrap();
+        task_history
+            .db
+            .execute_batch(
+                "CREATE TRIGGER fail BEFORE INSERT ON task_history WHEN NEW.hash = 'fail'
+                    BEGIN SELECT RAISE(ABORT, 'fail'); END",
+            )
+            .unwrap();
+
+        let result = task_history.record_task_runs(vec![task_run("123"), task_run("fail")]);
+        assert!(result.is_err());
+
+        task_history
+            .record_task_runs(vec![task_run("234"), task_run("345")])
+            .unwrap();
+        let hashes = task_history
+            .db
+            .prepare("SELECT hash FROM task_history ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        assert_eq!(hashes, vec!["234", "345"]);
+    }
+
+    #[test]
+    fn should_compute_timing_stats() {
+        let stats = timing_stats(vec![9.0, 2.0, 4.0, 4.0, 5.0, 4.0, 5.0, 7.0]);
+        assert_eq!(stats.count, 8);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.p50, 4.5);
+        assert!((stats.p90 - 7.6).abs() < 1e-9);
+        assert_eq!(stats.max, 9.0);
+        assert_eq!(stats.std_dev, 2.0);
+
+        let stats = timing_stats(vec![5.0]);
+        assert_eq!((stats.p50, stats.p90, stats.std_dev), (5.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn should_limit_timing_stats_to_recent_successful_runs() {
+        let db = NxDbConnection::new(Connection::open_in_memory().unwrap());
+        db.execute_batch(
+            "CREATE TABLE task_details (
+                hash TEXT PRIMARY KEY NOT NULL,
+                project TEXT NOT NULL,
+                target TEXT NOT NULL,
+                configuration TEXT
+            );
+            INSERT INTO task_details (hash, project, target) VALUES ('123', 'proj', 'build');",
+        )
+        .unwrap();
+        let task_history = NxTaskHistory::new(External::new(db)).unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let day = 24 * 60 * 60 * 1000;
+        let run = |code, duration, end| TaskRun {
+            hash: String::from("123"),
+            status: String::from("success"),
+            code,
+            start: end - duration,
+            end,
+        };
+        task_history
+            .record_task_runs(vec![
+                run(0, 1000, now - 40 * day),
+                run(0, 10, now - 2 * day),
+                run(1, 5, now - day),
+                run(0, 20, now),
+            ])
+            .unwrap();
+        let targets = vec![TaskTarget {
+            project: String::from("proj"),
+            target: String::from("build"),
+            configuration: None,
+        }];
+
+        let stats = |options| {
+            task_history
+                .get_task_timing_stats(targets.clone(), Some(options))
+                .unwrap()
+                .remove("proj:build")
+                .unwrap()
+        };
+        assert_eq!(stats(TaskTimingOptions::default()).count, 4);
+        assert_eq!(
+            stats(TaskTimingOptions {
+                last_days: Some(30),
+                successful_only: Some(true),
+                ..Default::default()
+            })
+            .mean,
+            15.0
+        );
+        assert_eq!(
+            stats(TaskTimingOptions {
+                last_runs: Some(2),
+                ..Default::default()
+            })
+            .max,
+            20.0
+        );
+    }
+
+    #[test]
+    fn should_score_flaky_targets_inside_the_window() {
+        let db = NxDbConnection::new(Connection::open_in_memory().unwrap());
+        db.execute_batch(
+            "CREATE TABLE task_details (
+                hash TEXT PRIMARY KEY NOT NULL,
+                project TEXT NOT NULL,
+                target TEXT NOT NULL,
+                configuration TEXT
+            );
+            INSERT INTO task_details (hash, project, target) VALUES
+                ('123', 'proj', 'build'),
+                ('234', 'proj', 'build'),
+                ('345', 'proj', 'test'),
+                ('456', 'proj', 'lint'),
+                ('567', 'proj', 'e2e');",
+        )
+        .unwrap();
+        let task_history = NxTaskHistory::new(External::new(db)).unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let day = 24 * 60 * 60 * 1000;
+        let run = |hash: &str, code, end| TaskRun {
+            hash: hash.to_string(),
+            status: String::from("success"),
+            code,
+            start: end - 1,
+            end,
+        };
+        task_history
+            .record_task_runs(vec![
+                run("123", 1, now - 3 * day),
+                run("123", 0, now - 2 * day),
+                run("234", 0, now - 2 * day),
+                run("234", 1, now - day),
+                // Forgotten, as it is outside of the window
+                run("345", 1, now - 40 * day),
+                run("345", 0, now - 39 * day),
+                run("456", 1, now - day),
+                run("456", 0, now),
+                // Flaky, as the failure before the window is followed by a success inside of it
+                run("567", 1, now - 20 * day),
+                run("567", 0, now - day),
+            ])
+            .unwrap();
+
+        let flaky_targets = task_history.get_flaky_targets(None).unwrap();
+        let scores = flaky_targets
+            .iter()
+            .map(|t| (t.target.as_str(), t.score))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scores,
+            vec![("proj:e2e", 1.0), ("proj:lint", 0.5), ("proj:build", 0.25)]
+        );
+        assert_eq!(flaky_targets[2].example_hashes, vec!["123"]);
+        assert_eq!(flaky_targets[2].last_flaky_at, now - 2 * day);
+
+        let flaky_targets = task_history
+            .get_flaky_targets(Some(FlakyTargetOptions {
+                targets: Some(vec![TaskTarget {
+                    project: String::from("proj"),
+                    target: String::from("test"),
+                    configuration: None,
+                }]),
+                window_days: Some(60),
+                ..Default::default()
+            }))
+            .unwrap();
+        assert_eq!(flaky_targets.len(), 1);
+        assert_eq!(flaky_targets[0].target, "proj:test");
+    }
+
+    #[test]
+    fn should_query_task_runs() {
+        let db = NxDbConnection::new(Connection::open_in_memory().unwrap());
+        db.execute_batch(
+            "CREATE TABLE task_details (
+                hash TEXT PRIMARY KEY NOT NULL,
+                project TEXT NOT NULL,
+                target TEXT NOT NULL,
+                configuration TEXT
+            );
+            INSERT INTO task_details (hash, project, target, configuration) VALUES
+                ('123', 'app', 'e2e', NULL),
+                ('234', 'app', 'e2e', 'ci'),
+                ('345', 'lib', 'build', NULL);",
+        )
+        .unwrap();
+        let task_history = NxTaskHistory::new(External::new(db)).unwrap();
+        let run = |hash: &str, status: &str, start, end| TaskRun {
+            hash: hash.to_string(),
+            status: status.to_string(),
+            code: if status == "failure" { 1 } else { 0 },
+            start,
+            end,
+        };
+        task_history
+            .record_task_runs(vec![
+                run("123", "failure", 0, 50),
+                run("123", "success", 100, 110),
+                run("234", "success", 200, 400),
+                run("345", "success", 300, 310),
+            ])
+            .unwrap();
+        let starts =
+            |result: TaskRunQueryResult| result.runs.iter().map(|r| r.start).collect::<Vec<_>>();
+
+        let result = task_history
+            .query_task_runs(TaskRunQuery {
+                project: Some(String::from("app")),
+                target: Some(String::from("e2e")),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(result.total, 3);
+        assert_eq!(result.runs[0].configuration.as_deref(), Some("ci"));
+        assert_eq!(starts(result), vec![200, 100, 0]);
+
+        let result = task_history
+            .query_task_runs(TaskRunQuery {
+                sort_by: Some(TaskRunSortField::Duration),
+                limit: Some(2),
+                offset: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(result.total, 4);
+        assert_eq!(starts(result), vec![0, 300]);
+
+        let result = task_history
+            .query_task_runs(TaskRunQuery {
+                status: Some(String::from("success")),
+                started_after: Some(100),
+                started_before: Some(300),
+                descending: Some(false),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(starts(result), vec![100, 200]);
+
+        let result = task_history
+            .query_task_runs(TaskRunQuery {
+                project: Some(String::from("app")),
+                without_configuration: Some(true),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(result.total, 2);
+        assert!(result.runs.iter().all(|r| r.configuration.is_none()));
+        assert_eq!(starts(result), vec![100, 0]);
+    }
 }
@@ -1,7 +1,26 @@
-import { TaskDetails, NxCache } from '../index';
+import {
+  CacheAccessMode,
+  CacheEntryFormat,
+  CachedResult,
+  CopyStrategy,
+  RestoreMode,
+  TaskDetails,
+  NxCache,
+  NxCacheOptions,
+} from '../index';
 import { join } from 'path';
 import { TempFs } from '../../internal-testing-utils/temp-fs';
-import { rmSync } from 'fs';
+import {
+  chmodSync,
+  cpSync,
+  existsSync,
+  readFileSync,
+  readdirSync,
+  rmSync,
+  statSync,
+  utimesSync,
+  writeFileSync,
+} from 'fs';
 import { getDbConnection } from '../../utils/db-connection';
 import { randomBytes } from 'crypto';
 
@@ -43,6 +62,38 @@ describe('Cache', () => {
     });
   });
 
+  const createCache = (
+    options?: NxCacheOptions,
+    cacheDir = '.cache',
+    dbName = `temp-db-${randomBytes(4).toString('hex')}`
+  ) =>
+    new NxCache(
+      tempFs.tempDir,
+      join(tempFs.tempDir, cacheDir),
+      getDbConnection({
+        directory: join(__dirname, dbOutputFolder),
+        dbName,
+      }),
+      false,
+      options
+    );
+
+  const putOutput = (nxCache: NxCache, hash = '123') => {
+    tempFs.createFileSync('dist/output.txt', `output contents ${hash}`);
+    nxCache.put(hash, `output ${hash}`, ['dist'], 0);
+  };
+
+  const expectRestoredOutput = async (
+    nxCache: NxCache,
+    result: CachedResult,
+    hash = '123'
+  ) => {
+    nxCache.copyFilesFromCache(result, ['dist']);
+    expect(await tempFs.readFile('dist/output.txt')).toEqual(
+      `output contents ${hash}`
+    );
+  };
+
   it('should store results into cache', async () => {
     const result = cache.get('123');
 
@@ -65,8 +116,439 @@ describe('Cache', () => {
     );
   });
 
+  it('should restore the outputs of each stored hash', async () => {
+    putOutput(cache, '123');
+    putOutput(cache, '234');
+
+    tempFs.removeFileSync('dist/output.txt');
+    await expectRestoredOutput(cache, cache.get('123'), '123');
+
+    tempFs.removeFileSync('dist/output.txt');
+    await expectRestoredOutput(cache, cache.get('234'), '234');
+  });
+
   it('should handle storing hashes that already exist in the cache', async () => {
     cache.put('123', 'output 123', ['dist'], 0);
     expect(() => cache.put('123', 'output 123', ['dist'], 0)).not.toThrow();
   });
+
+  it('should only store identical output files once', async () => {
+    tempFs.createFileSync('dist/a.txt', 'shared contents');
+    tempFs.createFileSync('dist/b.txt', 'shared contents');
+
+    cache.put('123', 'output 123', ['dist'], 0);
+    cache.put('234', 'output 234', ['dist'], 0);
+
+    const blobsDir = join(tempFs.tempDir, '.cache', 'blobs');
+    const blobs = readdirSync(blobsDir).flatMap((prefix) =>
+      readdirSync(join(blobsDir, prefix))
+    );
+    expect(blobs).toHaveLength(1);
+
+    tempFs.removeFileSync('dist/a.txt');
+    tempFs.removeFileSync('dist/b.txt');
+
+    cache.copyFilesFromCache(cache.get('234'), ['dist']);
+
+    expect(await tempFs.readFile('dist/a.txt')).toEqual('shared contents');
+    expect(await tempFs.readFile('dist/b.txt')).toEqual('shared contents');
+  });
+
+  it('should evict the least recently used entries over the budget', async () => {
+    putOutput(cache, '123');
+    putOutput(cache, '234');
+
+    const report = cache.removeOldCacheRecords({ maxEntries: 1 });
+
+    expect(report.entriesRemoved).toEqual(1);
+    expect(report.bytesFreed).toBeGreaterThan(0);
+    expect(cache.get('123')).toBeNull();
+    expect(cache.get('234')).not.toBeNull();
+  });
+
+  it('should store entries as single archives', async () => {
+    const archiveCache = createCache(
+      { entryFormat: CacheEntryFormat.Archive },
+      '.archive-cache'
+    );
+
+    putOutput(archiveCache);
+
+    expect(
+      existsSync(join(tempFs.tempDir, '.archive-cache', '123.tar.zst'))
+    ).toBeTruthy();
+
+    tempFs.removeFileSync('dist/output.txt');
+
+    const result = archiveCache.get('123');
+    await expectRestoredOutput(archiveCache, result);
+
+    expect(result.terminalOutput).toEqual('output 123');
+  });
+
+  it('should treat corrupted entries as a miss when verifying integrity', async () => {
+    const verifyingCache = createCache(
+      { verifyIntegrity: true },
+      '.verified-cache'
+    );
+
+    putOutput(verifyingCache);
+    expect(verifyingCache.get('123')).not.toBeNull();
+
+    writeFileSync(
+      join(tempFs.tempDir, '.verified-cache', '123', 'dist', 'output.txt'),
+      'corrupted'
+    );
+
+    expect(verifyingCache.get('123')).toBeNull();
+    expect(
+      existsSync(join(tempFs.tempDir, '.verified-cache', '123'))
+    ).toBeFalsy();
+  });
+
+  it('should share entries through a remote cache', async () => {
+    const remoteCache = { url: join(tempFs.tempDir, 'remote-cache') };
+    const firstCache = createCache({ remoteCache }, '.first-cache');
+    const secondCache = createCache({ remoteCache }, '.second-cache');
+
+    putOutput(firstCache);
+
+    expect(firstCache.storeToRemote(['123', '234'])).toEqual(['123']);
+    expect(firstCache.storeToRemote(['123'])).toEqual([]);
+
+    tempFs.removeFileSync('dist/output.txt');
+
+    const results = secondCache.retrieveFromRemote(['123', '234']);
+    expect(Object.keys(results)).toEqual(['123']);
+    expect(results['123'].terminalOutput).toEqual('output 123');
+
+    await expectRestoredOutput(secondCache, results['123']);
+  });
+
+  it('should export and import bundles with their task details', async () => {
+    putOutput(cache);
+
+    const bundlePath = join(tempFs.tempDir, 'bundle.tar');
+    expect(cache.exportBundle(bundlePath, { projects: ['other'] })).toEqual(
+      []
+    );
+    expect(
+      cache.exportBundle(bundlePath, { projects: ['proj'], targets: ['test'] })
+    ).toEqual(['123']);
+
+    const otherDbConnection = getDbConnection({
+      directory: join(__dirname, dbOutputFolder),
+      dbName: `temp-db-${randomBytes(4).toString('hex')}`,
+    });
+    new TaskDetails(otherDbConnection);
+    const otherCache = new NxCache(
+      tempFs.tempDir,
+      join(tempFs.tempDir, '.other-cache'),
+      otherDbConnection
+    );
+
+    expect(otherCache.importBundle(bundlePath)).toEqual(['123']);
+    expect(otherCache.importBundle(bundlePath)).toEqual([]);
+
+    tempFs.removeFileSync('dist/output.txt');
+    const result = otherCache.get('123');
+    await expectRestoredOutput(otherCache, result);
+
+    expect(result.terminalOutput).toEqual('output 123');
+  });
+
+  it('should restore outputs with the configured copy strategy', async () => {
+    const linkingCache = createCache(
+      { copyStrategy: CopyStrategy.Hardlink },
+      '.linked-cache'
+    );
+
+    putOutput(linkingCache);
+
+    // The outputs in the workspace are copied into the cache, never linked
+    const outputPath = join(tempFs.tempDir, 'dist/output.txt');
+    expect(statSync(outputPath).nlink).toEqual(1);
+    writeFileSync(outputPath, 'modified contents');
+
+    await expectRestoredOutput(linkingCache, linkingCache.get('123'));
+    if (process.platform !== 'win32') {
+      expect(statSync(outputPath).mode & 0o222).toEqual(0);
+    }
+  });
+
+  it('should only rewrite changed files in differential restore mode', async () => {
+    const differentialCache = createCache(
+      { restoreMode: RestoreMode.Differential },
+      '.differential-cache'
+    );
+
+    tempFs.createFileSync('dist/same.txt', 'same contents');
+    tempFs.createFileSync('dist/changed.txt', 'original contents');
+    differentialCache.put('123', 'output 123', ['dist'], 0);
+
+    tempFs.createFileSync('dist/changed.txt', 'modified contents');
+    tempFs.createFileSync('dist/extra.txt', 'extra contents');
+
+    const report = differentialCache.copyFilesFromCache(
+      differentialCache.get('123'),
+      ['dist']
+    );
+
+    expect(report.written).toEqual(['dist/changed.txt']);
+    expect(report.removed).toEqual(['dist/extra.txt']);
+    expect(report.unchanged).toEqual(1);
+    expect(await tempFs.readFile('dist/changed.txt')).toEqual(
+      'original contents'
+    );
+    expect(existsSync(join(tempFs.tempDir, 'dist/extra.txt'))).toBeFalsy();
+  });
+
+  it('should report the size of the cache per project and target', async () => {
+    putOutput(cache);
+
+    const stats = cache.getStats();
+
+    expect(stats.entryCount).toEqual(1);
+    expect(stats.totalSize).toBeGreaterThan(0);
+    expect(stats.usage).toEqual([
+      {
+        project: 'proj',
+        target: 'test',
+        entries: 1,
+        size: stats.totalSize,
+      },
+    ]);
+    expect(stats.oldestEntry.hash).toEqual('123');
+    expect(stats.newestEntry.hash).toEqual('123');
+  });
+
+  it('should sweep abandoned staging directories on startup', async () => {
+    putOutput(cache);
+    tempFs.createFileSync('.cache/staging/234-1-0/dist/output.txt', 'partial');
+
+    const stagingDir = join(tempFs.tempDir, '.cache', 'staging');
+    expect(readdirSync(stagingDir)).toEqual(['234-1-0']);
+
+    createCache();
+
+    expect(readdirSync(stagingDir)).toEqual([]);
+    expect(cache.get('123').terminalOutput).toEqual('output 123');
+  });
+
+  it('should report and repair mismatches between records and files', async () => {
+    putOutput(cache);
+    cache.put('234', 'output 234', ['dist'], 0);
+
+    expect(cache.fsck()).toEqual({
+      missingEntries: [],
+      untrackedEntries: [],
+      orphanedTerminalOutputs: [],
+      missingTaskDetails: ['234'],
+      mismatchedBlobReferences: [],
+      orphanedBlobs: [],
+      repaired: false,
+    });
+
+    rmSync(join(tempFs.tempDir, '.cache', '123'), { recursive: true });
+    tempFs.createFileSync('.cache/345/dist/output.txt', 'output contents 345');
+    tempFs.createFileSync('.cache/terminalOutputs/456', 'output 456');
+
+    const report = cache.fsck(true);
+    expect(report.missingEntries).toEqual(['123']);
+    expect(report.untrackedEntries).toEqual(['345']);
+    expect(report.orphanedTerminalOutputs).toEqual(['456']);
+    expect(report.missingTaskDetails).toEqual(['234']);
+    expect(report.mismatchedBlobReferences).toHaveLength(1);
+    expect(report.orphanedBlobs).toHaveLength(1);
+    expect(report.repaired).toBeTruthy();
+
+    expect(cache.fsck()).toEqual({
+      missingEntries: [],
+      untrackedEntries: [],
+      orphanedTerminalOutputs: [],
+      missingTaskDetails: [],
+      mismatchedBlobReferences: [],
+      orphanedBlobs: [],
+      repaired: false,
+    });
+    expect(cache.get('123')).toBeNull();
+    expect(cache.get('234')).toBeNull();
+  });
+
+  it('should keep blob references in sync when entries are replaced', async () => {
+    // Replacing an entry keeps its position, so the entry to evict is stored first
+    tempFs.createFileSync('dist/output.txt', 'output contents 123');
+    cache.put('234', 'output 234', ['dist'], 0);
+    cache.put('123', 'output 123', ['dist'], 0);
+    tempFs.createFileSync('dist/output.txt', 'replaced contents 123');
+    cache.put('123', 'output 123', ['dist'], 0);
+
+    // A leftover directory without a record is a miss which holds no references
+    cpSync(
+      join(tempFs.tempDir, '.cache', '234'),
+      join(tempFs.tempDir, '.cache', '345'),
+      { recursive: true }
+    );
+    expect(cache.get('345')).toBeNull();
+
+    const report = cache.fsck();
+    expect(report.untrackedEntries).toEqual(['345']);
+    expect(report.mismatchedBlobReferences).toEqual([]);
+    expect(report.orphanedBlobs).toEqual([]);
+
+    expect(cache.removeOldCacheRecords({ maxEntries: 1 }).entriesRemoved).toEqual(
+      1
+    );
+    tempFs.removeFileSync('dist/output.txt');
+    cache.copyFilesFromCache(cache.get('123'), ['dist']);
+    expect(await tempFs.readFile('dist/output.txt')).toEqual(
+      'replaced contents 123'
+    );
+  });
+
+  it('should keep pins and blob references when applying remote results again', async () => {
+    putOutput(cache);
+    expect(cache.pin('123', ['release'])).toBeTruthy();
+
+    const result = cache.get('123');
+    cache.applyRemoteCacheResults('123', result);
+    cache.applyRemoteCacheResults('123', result);
+
+    expect(cache.getEntriesByTag('release')).toEqual([
+      { hash: '123', pinned: true, tags: ['release'] },
+    ]);
+    const report = cache.fsck(true);
+    expect(report.mismatchedBlobReferences).toEqual([]);
+    expect(report.orphanedBlobs).toEqual([]);
+  });
+
+  it('should restore file modes and modification times', async () => {
+    tempFs.createFileSync('dist/run.sh', 'echo 123');
+    const scriptPath = join(tempFs.tempDir, 'dist/run.sh');
+    chmodSync(scriptPath, 0o755);
+    utimesSync(scriptPath, 1600000000, 1600000000);
+
+    cache.put('123', 'output 123', ['dist'], 0);
+    tempFs.removeFileSync('dist/run.sh');
+    cache.copyFilesFromCache(cache.get('123'), ['dist']);
+
+    const stats = statSync(scriptPath);
+    if (process.platform !== 'win32') {
+      expect(stats.mode & 0o777).toEqual(0o755);
+    }
+    expect(stats.mtimeMs).toEqual(1600000000 * 1000);
+  });
+
+  it('should keep pinned entries when evicting', async () => {
+    putOutput(cache);
+    cache.put('234', 'output 234', ['dist'], 0);
+
+    expect(cache.pin('123', ['release'])).toBeTruthy();
+    expect(cache.pin('345')).toBeFalsy();
+
+    const report = cache.removeOldCacheRecords({ maxAgeDays: 0, maxEntries: 0 });
+
+    expect(report.entriesRemoved).toEqual(1);
+    expect(cache.get('123')).not.toBeNull();
+    expect(cache.get('234')).toBeNull();
+    expect(cache.getEntriesByTag('release')).toEqual([
+      { hash: '123', pinned: true, tags: ['release'] },
+    ]);
+
+    expect(cache.unpinByTag('release')).toEqual(['123']);
+    expect(cache.getEntriesByTag('release')).toEqual([
+      { hash: '123', pinned: false, tags: ['release'] },
+    ]);
+  });
+
+  it('should not write to a read-only cache', async () => {
+    const readOnlyCache = createCache({ accessMode: CacheAccessMode.ReadOnly });
+
+    putOutput(readOnlyCache);
+
+    expect(readOnlyCache.get('123')).toBeNull();
+    expect(existsSync(join(tempFs.tempDir, '.cache', '123'))).toBeFalsy();
+  });
+
+  it('should look up missing entries in the cache layers', async () => {
+    const sharedCacheDir = join(tempFs.tempDir, 'shared-cache');
+    const layers = [{ path: sharedCacheDir }];
+
+    putOutput(createCache({ layers }, '.cache-a'));
+    expect(existsSync(join(sharedCacheDir, '123.tar.zst'))).toBeTruthy();
+
+    tempFs.removeFileSync('dist/output.txt');
+    const layeredCache = createCache({ layers }, '.cache-b');
+    const result = layeredCache.get('123');
+    expect(result.terminalOutput).toEqual('output 123');
+
+    await expectRestoredOutput(layeredCache, result);
+  });
+
+  it('should not promote entries from the cache layers into a read-only cache', async () => {
+    const sharedCacheDir = join(tempFs.tempDir, 'shared-cache');
+    const layers = [{ path: sharedCacheDir }];
+
+    putOutput(createCache({ layers }, '.cache-a'));
+
+    tempFs.removeFileSync('dist/output.txt');
+    const readOnlyCache = createCache(
+      { layers, accessMode: CacheAccessMode.ReadOnly },
+      '.cache-b'
+    );
+    const result = readOnlyCache.get('123');
+    expect(result.terminalOutput).toEqual('output 123');
+    await expectRestoredOutput(readOnlyCache, result);
+
+    expect(
+      existsSync(join(tempFs.tempDir, '.cache-b', '123.tar.zst'))
+    ).toBeFalsy();
+    expect(readOnlyCache.getStats().entryCount).toEqual(0);
+  });
+
+  it('should treat entries encrypted with another key as a miss', async () => {
+    const dbName = `temp-db-${randomBytes(4).toString('hex')}`;
+    const createEncryptedCache = (key: string) => {
+      process.env.NX_CLOUD_ENCRYPTION_KEY = key;
+      try {
+        return createCache({ encrypt: true }, '.cache', dbName);
+      } finally {
+        delete process.env.NX_CLOUD_ENCRYPTION_KEY;
+      }
+    };
+
+    const encryptedCache = createEncryptedCache('key-1');
+    putOutput(encryptedCache);
+
+    expect(encryptedCache.get('123').terminalOutput).toEqual('output 123');
+
+    // Neither the outputs nor the terminal output are stored in plaintext
+    const cacheDir = join(tempFs.tempDir, '.cache');
+    const cachedContents = readdirSync(cacheDir, { recursive: true })
+      .map((path) => join(cacheDir, path.toString()))
+      .filter((path) => statSync(path).isFile())
+      .map((path) => readFileSync(path).toString());
+    expect(cachedContents).not.toHaveLength(0);
+    for (const contents of cachedContents) {
+      expect(contents).not.toContain('output contents 123');
+      expect(contents).not.toContain('output 123');
+    }
+    expect(createEncryptedCache('key-2').get('123')).toBeNull();
+  });
+
+  it('should look up many entries at once', async () => {
+    putOutput(cache);
+    cache.put('234', 'output 234', ['dist'], 1);
+
+    expect(cache.containsMany(['123', '345', '234'])).toEqual(['123', '234']);
+
+    const results = cache.getMany(['123', '234', '345']);
+    expect(Object.keys(results).sort()).toEqual(['123', '234']);
+    expect(results['234'].code).toEqual(1);
+    expect(results['234'].terminalOutput).toEqual('');
+
+    expect(cache.getMany(['123'], true)['123'].terminalOutput).toEqual(
+      'output 123'
+    );
+  });
 });
@@ -1,22 +1,31 @@
-import { TaskDetails, NxTaskHistory } from '../index';
+import {
+  DbOpenMode,
+  TaskDetails,
+  TaskRunSortField,
+  NxTaskHistory,
+  maintainNxDb,
+} from '../index';
 import { join } from 'path';
 import { TempFs } from '../../internal-testing-utils/temp-fs';
 import { rmSync } from 'fs';
 import { getDbConnection } from '../../utils/db-connection';
 import { randomBytes } from 'crypto';
+import { version as NX_VERSION } from '../../../package.json';
 
 const dbOutputFolder = 'temp-db-task';
 describe('NxTaskHistory', () => {
   let taskHistory: NxTaskHistory;
   let tempFs: TempFs;
   let taskDetails: TaskDetails;
+  let dbName: string;
 
   beforeEach(() => {
     tempFs = new TempFs('task-history');
 
+    dbName = `temp-db-${randomBytes(4).toString('hex')}`;
     const dbConnection = getDbConnection({
       directory: join(__dirname, dbOutputFolder),
-      dbName: `temp-db-${randomBytes(4).toString('hex')}`,
+      dbName,
     });
     taskHistory = new NxTaskHistory(dbConnection);
     taskDetails = new TaskDetails(dbConnection);
@@ -123,4 +132,195 @@ describe('NxTaskHistory', () => {
     ]);
     expect(r['proj:build:production']).toEqual(60 * 60 * 1000);
   });
+
+  it('should query task runs', () => {
+    const end = Date.now();
+    taskHistory.recordTaskRuns([
+      { hash: '123', code: 1, status: 'failure', start: end - 300, end },
+      { hash: '123', code: 0, status: 'success', start: end - 100, end },
+      { hash: '234', code: 0, status: 'success', start: end - 200, end },
+    ]);
+
+    const result = taskHistory.queryTaskRuns({
+      project: 'proj',
+      target: 'build',
+      status: 'success',
+      sortBy: TaskRunSortField.Duration,
+      limit: 1,
+    });
+
+    expect(result.total).toEqual(2);
+    expect(result.runs).toEqual([
+      {
+        hash: '234',
+        project: 'proj',
+        target: 'build',
+        configuration: 'production',
+        status: 'success',
+        code: 0,
+        start: end - 200,
+        end,
+      },
+    ]);
+  });
+
+  it('should get task timing statistics', () => {
+    const end = Date.now();
+    taskHistory.recordTaskRuns([
+      { hash: '123', code: 0, status: 'success', start: end - 100, end },
+      { hash: '123', code: 0, status: 'success', start: end - 300, end },
+      { hash: '234', code: 1, status: 'failure', start: end - 5000, end },
+    ]);
+    const targets = [
+      { project: 'proj', target: 'build', configuration: 'production' },
+    ];
+
+    const stats = taskHistory.getTaskTimingStats(targets);
+    expect(stats['proj:build:production'].count).toEqual(3);
+    expect(stats['proj:build:production'].max).toEqual(5000);
+
+    const successful = taskHistory.getTaskTimingStats(targets, {
+      successfulOnly: true,
+    });
+    expect(successful['proj:build:production']).toEqual({
+      count: 2,
+      mean: 200,
+      p50: 200,
+      p90: 280,
+      max: 300,
+      stdDev: 100,
+    });
+  });
+
+  it('should remove old task runs during maintenance', () => {
+    const day = 1000 * 60 * 60 * 24;
+    taskHistory.recordTaskRuns([
+      {
+        hash: '123',
+        code: 0,
+        status: 'success',
+        start: Date.now() - 40 * day,
+        end: Date.now() - 40 * day,
+      },
+      {
+        hash: '234',
+        code: 0,
+        status: 'success',
+        start: Date.now() - 1000 * 60 * 60,
+        end: Date.now(),
+      },
+    ]);
+
+    const report = maintainNxDb(
+      join(__dirname, dbOutputFolder),
+      NX_VERSION,
+      dbName,
+      { maxTaskRunAgeDays: 30 }
+    );
+
+    expect(report.taskRunsRemoved).toEqual(1);
+    expect(report.taskDetailsRemoved).toEqual(1);
+    expect(report.integrityErrors).toEqual([]);
+    expect(report.rebuilt).toBeFalsy();
+  });
+
+  it('should read task history without writing to a read-only database', () => {
+    taskHistory.recordTaskRuns([
+      {
+        hash: '123',
+        code: 1,
+        status: 'failure',
+        start: Date.now() - 1000 * 60 * 60,
+        end: Date.now(),
+      },
+      {
+        hash: '123',
+        code: 0,
+        status: 'success',
+        start: Date.now() - 1000 * 60 * 60,
+        end: Date.now(),
+      },
+    ]);
+
+    const readOnlyTaskHistory = new NxTaskHistory(
+      getDbConnection({
+        directory: join(__dirname, dbOutputFolder),
+        dbName,
+        mode: DbOpenMode.ReadOnly,
+      })
+    );
+    readOnlyTaskHistory.recordTaskRuns([
+      {
+        hash: '234',
+        code: 1,
+        status: 'failure',
+        start: Date.now() - 1000 * 60 * 60,
+        end: Date.now(),
+      },
+    ]);
+    taskHistory.recordTaskRuns([
+      {
+        hash: '234',
+        code: 0,
+        status: 'success',
+        start: Date.now() - 1000 * 60 * 60,
+        end: Date.now(),
+      },
+    ]);
+
+    const r = readOnlyTaskHistory.getFlakyTasks(['123', '234']);
+    expect(r).toEqual(['123']);
+  });
+
+  it('should not open databases which do not exist as read-only', () => {
+    expect(() =>
+      getDbConnection({
+        directory: join(__dirname, dbOutputFolder),
+        dbName: 'missing',
+        mode: DbOpenMode.ReadOnly,
+      })
+    ).toThrow();
+  });
+
+  it('should keep task history of in-memory databases in memory', () => {
+    const inMemoryTaskHistory = new NxTaskHistory(
+      getDbConnection({
+        dbName: `in-memory-${dbName}`,
+        mode: DbOpenMode.InMemory,
+      })
+    );
+    const inMemoryTaskDetails = new TaskDetails(
+      getDbConnection({
+        dbName: `in-memory-${dbName}`,
+        mode: DbOpenMode.InMemory,
+      })
+    );
+    inMemoryTaskDetails.recordTaskDetails([
+      {
+        hash: '123',
+        project: 'proj',
+        target: 'build',
+        configuration: 'production',
+      },
+    ]);
+    inMemoryTaskHistory.recordTaskRuns([
+      {
+        hash: '123',
+        code: 1,
+        status: 'failure',
+        start: Date.now() - 1000 * 60 * 60,
+        end: Date.now(),
+      },
+      {
+        hash: '123',
+        code: 0,
+        status: 'success',
+        start: Date.now() - 1000 * 60 * 60,
+        end: Date.now(),
+      },
+    ]);
+
+    expect(inMemoryTaskHistory.getFlakyTasks(['123'])).toEqual(['123']);
+    expect(taskHistory.getFlakyTasks(['123'])).toEqual([]);
+  });
 });
@@ -1,4 +1,4 @@
-import { connectToNxDb, ExternalObject } from '../native';
+import { connectToNxDb, DbOpenMode, ExternalObject } from '../native';
 import { workspaceDataDirectory } from './cache-directory';
 import { version as NX_VERSION } from '../../package.json';
 
@@ -8,12 +8,15 @@ export function getDbConnection(
   opts: {
     directory?: string;
     dbName?: string;
+    mode?: DbOpenMode;
   } = {}
 ) {
   opts.directory ??= workspaceDataDirectory;
-  const key = `${opts.directory}:${opts.dbName ?? 'default'}`;
+  const key = `${opts.directory}:${opts.dbName ?? 'default'}:${
+    opts.mode ?? DbOpenMode.ReadWrite
+  }`;
   const connection = getEntryOrSet(dbConnectionMap, key, () =>
-    connectToNxDb(opts.directory, NX_VERSION, opts.dbName)
+    connectToNxDb(opts.directory, NX_VERSION, opts.dbName, opts.mode)
   );
   return connection;
 }
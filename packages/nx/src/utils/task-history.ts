@@ -1,6 +1,20 @@
 import { daemonClient } from '../daemon/client/client';
 import { isOnDaemon } from '../daemon/is-on-daemon';
-import { IS_WASM, NxTaskHistory, TaskRun, TaskTarget } from '../native';
+import {
+  FlakyTarget,
+  FlakyTargetOptions,
+  IS_WASM,
+  NxTaskHistory,
+  TaskGraph,
+  TaskGraphEstimate,
+  TaskGraphEstimateOptions,
+  TaskRun,
+  TaskRunQuery,
+  TaskRunQueryResult,
+  TaskTarget,
+  TaskTimingOptions,
+  TaskTimingStats,
+} from '../native';
 import { getDbConnection } from './db-connection';
 
 export class TaskHistory {
@@ -20,6 +34,52 @@ export class TaskHistory {
     return await daemonClient.getEstimatedTaskTimings(targets);
   }
 
+  /**
+   * This function returns statistics of the durations of historical runs per task
+   * @param targets
+   * @param options limit the runs to the most recent or successful ones
+   * @returns a map where key is task id (project:target:configuration), value is the count, mean, p50, p90, max and standard deviation of the durations
+   */
+  async getTaskTimingStats(
+    targets: TaskTarget[],
+    options?: TaskTimingOptions
+  ): Promise<Record<string, TaskTimingStats>> {
+    if (isOnDaemon() || !daemonClient.enabled()) {
+      return this.taskHistory.getTaskTimingStats(targets, options);
+    }
+    return await daemonClient.getTaskTimingStats(targets, options);
+  }
+
+  /**
+   * This function returns the historical runs of tasks
+   * @param query filters, sorting and pagination of the runs
+   * @returns a page of runs with the details of their tasks, and the number of runs matching the filters
+   */
+  async queryTaskRuns(query: TaskRunQuery): Promise<TaskRunQueryResult> {
+    if (isOnDaemon() || !daemonClient.enabled()) {
+      return this.taskHistory.queryTaskRuns(query);
+    }
+    return await daemonClient.queryTaskRuns(query);
+  }
+
+  /**
+   * This function estimates how long a task graph takes to run
+   * @param taskGraph
+   * @param parallel the number of tasks which run at the same time
+   * @param options which runs the durations are estimated from
+   * @returns the critical path, the expected wall-clock time, and when every task is expected to start and finish
+   */
+  async estimateTaskGraph(
+    taskGraph: TaskGraph,
+    parallel: number,
+    options?: TaskGraphEstimateOptions
+  ): Promise<TaskGraphEstimate> {
+    if (isOnDaemon() || !daemonClient.enabled()) {
+      return this.taskHistory.estimateTaskGraph(taskGraph, parallel, options);
+    }
+    return await daemonClient.estimateTaskGraph(taskGraph, parallel, options);
+  }
+
   async getFlakyTasks(hashes: string[]) {
     if (isOnDaemon() || !daemonClient.enabled()) {
       return this.taskHistory.getFlakyTasks(hashes);
@@ -27,6 +87,18 @@ export class TaskHistory {
     return await daemonClient.getFlakyTasks(hashes);
   }
 
+  /**
+   * This function scores how often targets succeed right after failing with the same hash
+   * @param options the targets and the number of days of runs to score
+   * @returns the flaky targets, flakiest first
+   */
+  async getFlakyTargets(options?: FlakyTargetOptions): Promise<FlakyTarget[]> {
+    if (isOnDaemon() || !daemonClient.enabled()) {
+      return this.taskHistory.getFlakyTargets(options);
+    }
+    return await daemonClient.getFlakyTargets(options);
+  }
+
   async recordTaskRuns(taskRuns: TaskRun[]) {
     if (isOnDaemon() || !daemonClient.enabled()) {
       return this.taskHistory.recordTaskRuns(taskRuns);